@@ -0,0 +1,104 @@
+use proc_macro::TokenStream;
+use quote::{quote, ToTokens};
+use syn::{parse_macro_input, Attribute, ItemFn, LitStr, Meta};
+
+/// Turns a function into a `#[test]` that is recorded into an AppMap.
+///
+/// The AppMap layer is installed as the default subscriber while the test body runs. The map is
+/// named after the module path and name of the test, gets a `test_status` depending on whether
+/// the test panicked or returned an error, and is written to `<appmap_dir>/tests/`. A
+/// `#[should_panic]` test is recorded as succeeded if it panics as expected.
+///
+/// ```ignore
+/// #[appmap_test]
+/// fn parses_config() {
+///     parse_config("appmap.yml");
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn appmap_test(_args: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    if let Some(asyncness) = function.sig.asyncness {
+        return syn::Error::new_spanned(asyncness, "use #[appmap_tokio_test] for async tests")
+            .to_compile_error()
+            .into();
+    }
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+    let name = sig.ident.to_string();
+    let expected_panic = match expected_panic(&attrs) {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    quote! {
+        #[test]
+        #(#attrs)*
+        #vis #sig {
+            ::appmap_tracing_test::test_support::run_test(module_path!(), #name, #expected_panic, move || #block)
+        }
+    }
+    .into()
+}
+
+/// Like [macro@appmap_test], for async tests run by `#[tokio::test]`.
+///
+/// Arguments are passed on to `#[tokio::test]`. The layer is installed as the default
+/// subscriber of the thread running the test, so only spans of tasks running on that thread
+/// are recorded. This is always the case with the default current thread runtime.
+#[proc_macro_attribute]
+pub fn appmap_tokio_test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    if function.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(function.sig.fn_token, "the test function must be async")
+            .to_compile_error()
+            .into();
+    }
+    let args = proc_macro2::TokenStream::from(args);
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+    let name = sig.ident.to_string();
+    let expected_panic = match expected_panic(&attrs) {
+        Ok(x) => x,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    quote! {
+        #[::tokio::test(#args)]
+        #(#attrs)*
+        #vis #sig {
+            ::appmap_tracing_test::test_support::run_async_test(module_path!(), #name, #expected_panic, async move #block).await
+        }
+    }
+    .into()
+}
+
+/// The message the test has to panic with according to its `#[should_panic]` attribute, as an
+/// `Option<&str>` expression: empty if any panic is expected, `None` without the attribute.
+fn expected_panic(attrs: &[Attribute]) -> syn::Result<proc_macro2::TokenStream> {
+    let Some(attr) = attrs.iter().find(|x| x.path().is_ident("should_panic")) else {
+        return Ok(quote!(None));
+    };
+    let expected = match &attr.meta {
+        Meta::Path(_) => String::new(),
+        Meta::NameValue(x) => syn::parse2::<LitStr>(x.value.to_token_stream())?.value(),
+        Meta::List(_) => {
+            let mut expected = String::new();
+            attr.parse_nested_meta(|meta| {
+                if !meta.path.is_ident("expected") {
+                    return Err(meta.error("expected `expected = \"...\"`"));
+                }
+                expected = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            })?;
+            expected
+        }
+    };
+    Ok(quote!(Some(#expected)))
+}
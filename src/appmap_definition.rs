@@ -1,33 +1,15 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
 use tracing::{info, instrument};
 
-pub use event_id::EventId;
+pub use event_id::{EventId, ObjectId};
 
-use crate::appmap_definition::event_id::ObjectId;
-
-//region todo objects
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct MetadataObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct ExceptionReturnObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct HttpServerRequestCallObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct HttpServerResponseCallObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct HttpClientRequestCallObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct HttpClientResponseCallObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct SqlQueryCallObject {}
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct MessageCallObject {}
-//endregion
-
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AppMapObject {
     pub version: String,
 
@@ -43,20 +25,175 @@ pub struct AppMapObject {
     #[serde(rename = "eventUpdates")]
     pub event_updates: Option<HashMap<u32, EventObject>>,
 }
+impl AppMapObject {
+    pub fn read_from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let data = serde_json::from_reader(BufReader::new(file))?;
+        Ok(data)
+    }
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+//region metadata
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct MetadataObject {
+    ///Optional name of the AppMap. Example: "Users profile settings page can be loaded".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub name: Option<String>,
+    ///Optional list of arbitrary labels describing the AppMap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub labels: Option<Vec<String>>,
+    ///Optional name of the app that was recorded. Example: "myapp".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub app: Option<String>,
+    ///Optional description of the programming language in which the app is written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub language: Option<LanguageObject>,
+    ///Optional list of frameworks which were used by the app.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub frameworks: Option<Vec<FrameworkObject>>,
+    ///Optional description of the client which produced the AppMap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub client: Option<ClientObject>,
+    ///Optional description of the method used to record the AppMap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub recorder: Option<RecorderObject>,
+    ///Optional description of the function whose execution was recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub recording: Option<RecordingObject>,
+    ///Optional status of the test case which was recorded. Must be "succeeded" or "failed".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub test_status: Option<TestStatus>,
+    ///Optional description of why the recorded test case failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub test_failure: Option<TestFailureObject>,
+    ///Optional description of the exception which ended the recording.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub exception: Option<MetadataExceptionObject>,
+    ///Optional number of events which were dropped to stay within the limits of the recording,
+    /// so the map is partial. Not part of the AppMap specification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub dropped_events: Option<u64>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct LanguageObject {
+    ///Required name of the language. Example: "rust".
+    pub name: String,
+    ///Optional name of the language engine or runtime.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub engine: Option<String>,
+    ///Optional version of the language. Example: "1.71.0".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub version: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct FrameworkObject {
+    ///Required name of the framework. Example: "tokio".
+    pub name: String,
+    ///Optional version of the framework. Example: "1.29.1".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub version: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ClientObject {
+    ///Required name of the client. Example: "appmap_tracing_test".
+    pub name: String,
+    ///Required URL of the client.
+    pub url: String,
+    ///Optional version of the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub version: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RecorderObject {
+    ///Required name of the recorder. Example: "tracing_layer".
+    pub name: String,
+    ///Optional type of the recorder. Example: "tests", "requests", "remote" or "process".
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub type_: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RecordingObject {
+    ///Required name of the class which defines the recorded function.
+    pub defined_class: String,
+    ///Required name of the recorded function.
+    pub method_id: String,
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum TestStatus {
+    Succeeded,
+    Failed,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TestFailureObject {
+    ///Required failure message.
+    pub message: String,
+    ///Optional location of the failure, as a path and line number separated by a colon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub location: Option<String>,
+}
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct MetadataExceptionObject {
+    ///Required name of the exception class. Example: "panic".
+    pub class: String,
+    ///Optional exception message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub message: Option<String>,
+}
+//endregion
 //region events
+pub mod call_tree;
+pub mod diff;
 mod event_id;
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub mod findings;
+pub mod merge;
+pub mod prune;
+pub mod rules;
+pub mod source_info;
+pub mod stats;
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct EventObject {
     //region common
     ///Required unique identifier. Example: 23522.
     pub id: EventId,
     ///Required identifier of the execution thread. Example: 70340688724000.
     pub thread_id: u32,
+    ///Optional time at which the event happened, in seconds since the unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub timestamp: Option<f64>,
     //endregion
     #[serde(flatten)]
     pub event: EventObjectType,
 }
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "event")]
 #[serde(rename_all = "camelCase")]
 pub enum EventObjectType {
@@ -64,14 +201,16 @@ pub enum EventObjectType {
     Return(ReturnObject),
 }
 //region Return Objects
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ReturnObject {
     ///Required id of the "call" event corresponding to this "return".
-    parent_id: u32,
+    pub parent_id: EventId,
     ///Optional elapsed time in seconds of this function call.
-    elapsed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub elapsed: Option<f64>,
     #[serde(flatten)]
-    data: ReturnObjectType,
+    pub data: ReturnObjectType,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(untagged)]
@@ -95,6 +234,11 @@ pub struct FunctionReturnObject {
     #[serde(default)]
     pub exceptions: Option<Vec<ExceptionObject>>,
 }
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ExceptionReturnObject {
+    ///Required exceptions causing the function to exit. The cause of an exception is the next one.
+    pub exceptions: Vec<ExceptionObject>,
+}
 //endregion
 //region call objects
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -122,6 +266,12 @@ pub struct CallObject {
     ///Required flag if the method is class-scoped (static) or instance-scoped. Must be true or false. Example: true.
     #[serde(rename = "static")]
     pub is_static: bool,
+    ///Optional ids of the call events which caused this call without it being nested in them,
+    /// like the spawn event of the task it runs in or calls it follows from. Not part of the
+    /// AppMap specification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub caused_by: Option<Vec<EventId>>,
 
     #[serde(flatten)]
     pub type_: CallObjectType,
@@ -140,6 +290,138 @@ pub enum CallObjectType {
     SqlQuery(SqlQueryCallObject),
     Message(MessageCallObject),
 }
+impl CallObjectType {
+    /// The kind of call in snake case. Example: "http_client_request".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallObjectType::Normal => "normal",
+            CallObjectType::Function => "function",
+            CallObjectType::HttpServerRequest(_) => "http_server_request",
+            CallObjectType::HttpServerResponse(_) => "http_server_response",
+            CallObjectType::HttpClientRequest(_) => "http_client_request",
+            CallObjectType::HttpClientResponse(_) => "http_client_response",
+            CallObjectType::SqlQuery(_) => "sql_query",
+            CallObjectType::Message(_) => "message",
+        }
+    }
+    /// Short description of HTTP, SQL and message calls. Example: "GET /api/users".
+    pub fn description(&self) -> Option<String> {
+        match self {
+            CallObjectType::Normal | CallObjectType::Function => None,
+            CallObjectType::HttpServerRequest(x) => {
+                let request = &x.http_server_request;
+                let path = request
+                    .normalized_path_info
+                    .as_ref()
+                    .unwrap_or(&request.path_info);
+                Some(format!("{} {}", request.request_method, path))
+            }
+            CallObjectType::HttpServerResponse(x) => {
+                Some(x.http_server_response.status.to_string())
+            }
+            CallObjectType::HttpClientRequest(x) => Some(format!(
+                "{} {}",
+                x.http_client_request.request_method, x.http_client_request.url
+            )),
+            CallObjectType::HttpClientResponse(x) => {
+                Some(x.http_client_response.status.to_string())
+            }
+            CallObjectType::SqlQuery(x) => Some(x.sql_query.sql.clone()),
+            CallObjectType::Message(x) => Some(
+                x.message
+                    .iter()
+                    .map(|x| x.value.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        }
+    }
+}
+//endregion
+//region typed call objects
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpServerRequestCallObject {
+    pub http_server_request: HttpServerRequestObject,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpServerRequestObject {
+    ///Required HTTP method. Example: "POST".
+    pub request_method: String,
+    ///Required path portion of the URL. Example: "/api/user/alice".
+    pub path_info: String,
+    ///Optional path with the parameters replaced by their names. Example: "/api/user/{name}".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub normalized_path_info: Option<String>,
+    ///Optional HTTP protocol and version. Example: "HTTP/1.1".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub protocol: Option<String>,
+    ///Optional request headers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub headers: Option<BTreeMap<String, String>>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpServerResponseCallObject {
+    pub http_server_response: HttpResponseObject,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpClientRequestCallObject {
+    pub http_client_request: HttpClientRequestObject,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpClientRequestObject {
+    ///Required HTTP method. Example: "GET".
+    pub request_method: String,
+    ///Required URL of the request without the query string. Example: "https://example.com/api".
+    pub url: String,
+    ///Optional request headers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub headers: Option<BTreeMap<String, String>>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpClientResponseCallObject {
+    pub http_client_response: HttpResponseObject,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct HttpResponseObject {
+    ///Required HTTP status code. Example: 200.
+    pub status: u16,
+    ///Optional MIME type of the response body. Example: "application/json".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    ///Optional response headers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub headers: Option<BTreeMap<String, String>>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SqlQueryCallObject {
+    pub sql_query: SqlQueryObject,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct SqlQueryObject {
+    ///Required name of the database. Example: "postgresql".
+    pub database_type: String,
+    ///Required SQL query string.
+    pub sql: String,
+    ///Optional query plan of the query.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub explain_sql: Option<String>,
+    ///Optional version of the database server. Example: "15.3".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub server_version: Option<String>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct MessageCallObject {
+    ///Required list of the parameters of the message.
+    pub message: Vec<ParameterObject>,
+}
 //endregion
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
@@ -147,19 +429,29 @@ pub struct ParameterObject {
     ///
     ///
     ///Recommended name of the parameter. Example: "login".
-    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub name: Option<String>,
     /// Recommended unique id of the object. Example: 70340693307040
-    object_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub object_id: Option<ObjectId>,
     ///Required fully qualified class or type name of the object. Example: "MyApp::User".
-    class: String,
+    pub class: String,
     ///Required string describing the object. This is not a strict JSON serialization, but rather a display string which is intended for the user. These strings should be trimmed in length to 100 characters. Example: "MyApp user 'alice'"
-    value: String,
+    pub value: String,
     /// Recommended number of elements in an array or hash object. Example. "5".
-    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub size: Option<usize>,
     /// Optional schema indicating property names and types of hash and hash-like objects. Each entry is a name, class and optional nested properties or items.
-    properties: Option<Vec<PropertiesObject>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub properties: Option<Vec<PropertiesObject>>,
     /// Optional schema indicating element types of array and array-like objects. Each entry is a class and optional nested properties or items.
-    items: Option<Vec<ItemObject>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub items: Option<Vec<ItemObject>>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct PropertiesObject {
@@ -177,10 +469,19 @@ pub struct ItemObject {
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct ExceptionObject {
+    ///Required fully qualified class or type name of the exception. Example: "panic".
     pub class: String,
+    ///Required exception message.
     pub message: String,
+    ///Required unique id of the exception.
     pub object_id: ObjectId,
+    ///Optional path of the file where the exception was raised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub path: Option<PathBuf>,
+    ///Optional line number where the exception was raised.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
     pub lineno: Option<usize>,
 }
 
@@ -231,6 +532,11 @@ pub struct FunctionCodeObject {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub source: Option<String>,
+    ///Optional number of calls of the function that were counted but not recorded because it
+    /// reached its call cap. Not part of the AppMap specification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub unrecorded_calls: Option<u64>,
 }
 
 //endregion
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::appmap_definition::*;
+
+/// The call/return events of an [AppMapObject] arranged as one call tree per thread.
+///
+/// Calls are nested under the call that was open on the same thread when they happened. A
+/// return closes its call (found through `parent_id`) together with every call that was opened
+/// after it and never returned.
+#[derive(Debug, Clone)]
+pub struct CallTree<'a> {
+    pub nodes: Vec<CallNode<'a>>,
+    pub roots: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallNode<'a> {
+    ///Index of the call event in `events`.
+    pub call_index: usize,
+    pub call: &'a EventObject,
+    pub call_object: &'a CallObject,
+    ///Index of the return event in `events`, if the call returned.
+    pub return_index: Option<usize>,
+    pub return_event: Option<&'a EventObject>,
+    pub return_object: Option<&'a ReturnObject>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    ///Number of calls enclosing this one. Roots have a depth of 0.
+    pub depth: usize,
+}
+
+impl<'a> CallTree<'a> {
+    pub fn new(data: &'a AppMapObject) -> Self {
+        let mut tree = CallTree {
+            nodes: vec![],
+            roots: vec![],
+        };
+        let mut stacks: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut by_event_id: HashMap<EventId, usize> = HashMap::new();
+
+        for (index, event) in data.events.iter().enumerate() {
+            match &event.event {
+                EventObjectType::Call(call) => {
+                    let stack = stacks.entry(event.thread_id).or_default();
+                    let parent = stack.last().copied();
+                    let node_index = tree.nodes.len();
+                    tree.nodes.push(CallNode {
+                        call_index: index,
+                        call: event,
+                        call_object: call,
+                        return_index: None,
+                        return_event: None,
+                        return_object: None,
+                        parent,
+                        children: vec![],
+                        depth: stack.len(),
+                    });
+                    match parent {
+                        Some(parent) => tree.nodes[parent].children.push(node_index),
+                        None => tree.roots.push(node_index),
+                    }
+                    stack.push(node_index);
+                    by_event_id.insert(event.id, node_index);
+                }
+                EventObjectType::Return(ret) => {
+                    let Some(&node_index) = by_event_id.get(&ret.parent_id) else {
+                        continue;
+                    };
+                    let node = &mut tree.nodes[node_index];
+                    if node.return_index.is_some() {
+                        continue;
+                    }
+                    node.return_index = Some(index);
+                    node.return_event = Some(event);
+                    node.return_object = Some(ret);
+
+                    let stack = stacks.entry(node.call.thread_id).or_default();
+                    if let Some(position) = stack.iter().rposition(|x| *x == node_index) {
+                        stack.truncate(position);
+                    }
+                }
+            }
+        }
+        tree
+    }
+
+    pub fn node_for_event(&self, id: EventId) -> Option<&CallNode<'a>> {
+        self.nodes.iter().find(|node| node.call.id == id)
+    }
+
+    /// Iterates over the node indices of the subtree starting at `index` in depth first order,
+    /// including `index` itself.
+    pub fn descendants(&self, index: usize) -> Vec<usize> {
+        let mut result = vec![];
+        let mut pending = vec![index];
+        while let Some(current) = pending.pop() {
+            result.push(current);
+            pending.extend(self.nodes[current].children.iter().rev());
+        }
+        result
+    }
+
+    /// The chain of node indices from the root down to (and including) `index`.
+    pub fn stack(&self, index: usize) -> Vec<usize> {
+        let mut result = vec![index];
+        let mut current = index;
+        while let Some(parent) = self.nodes[current].parent {
+            result.push(parent);
+            current = parent;
+        }
+        result.reverse();
+        result
+    }
+
+    pub fn elapsed(&self, index: usize) -> Option<f64> {
+        self.nodes[index].return_object.and_then(|x| x.elapsed)
+    }
+
+    /// Elapsed time of the call minus the elapsed time of its direct children.
+    pub fn self_elapsed(&self, index: usize) -> Option<f64> {
+        let total = self.elapsed(index)?;
+        let children: f64 = self.nodes[index]
+            .children
+            .iter()
+            .filter_map(|child| self.elapsed(*child))
+            .sum();
+        Some((total - children).max(0.0))
+    }
+
+    /// Start and end time of every node in seconds.
+    ///
+    /// Event timestamps are used where they exist, relative to the earliest timestamp in the
+    /// map. Calls without a timestamp start where their previous sibling ended (or where their
+    /// parent started) and last for their elapsed time.
+    pub fn timings(&self) -> Vec<(f64, f64)> {
+        let origin = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.call.timestamp)
+            .reduce(f64::min)
+            .unwrap_or(0.0);
+        let mut timings = vec![(0.0, 0.0); self.nodes.len()];
+        let mut next_root_start: HashMap<u32, f64> = HashMap::new();
+        for root in self.roots.iter() {
+            let cursor = next_root_start
+                .entry(self.nodes[*root].call.thread_id)
+                .or_insert(0.0);
+            *cursor = self.layout(*root, *cursor, origin, &mut timings);
+        }
+        timings
+    }
+
+    fn layout(&self, index: usize, cursor: f64, origin: f64, timings: &mut [(f64, f64)]) -> f64 {
+        let node = &self.nodes[index];
+        let start = node.call.timestamp.map(|x| x - origin).unwrap_or(cursor);
+        let mut child_cursor = start;
+        for child in node.children.iter() {
+            child_cursor = self.layout(*child, child_cursor, origin, timings);
+        }
+        let returned = node.return_event.and_then(|x| x.timestamp);
+        let end = match (self.elapsed(index), returned) {
+            (Some(elapsed), _) => start + elapsed,
+            (None, Some(returned)) => returned - origin,
+            (None, None) => child_cursor,
+        };
+        timings[index] = (start, end);
+        end
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+use crate::node_functions::collect_functions_in_tree;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffOptions {
+    ///Relative increase of the mean elapsed time of a function above which it is reported as a
+    /// regression. Example: 0.2 reports functions that got more than 20% slower.
+    pub elapsed_threshold: f64,
+}
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            elapsed_threshold: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AppMapDiff {
+    pub added_functions: Vec<FunctionRef>,
+    pub removed_functions: Vec<FunctionRef>,
+    pub changed_functions: Vec<FunctionChange>,
+    pub added_calls: Vec<CallEdge>,
+    pub removed_calls: Vec<CallEdge>,
+    ///HTTP and SQL events whose number of occurrences differs between the two maps.
+    pub changed_events: Vec<EventChange>,
+    pub elapsed_regressions: Vec<ElapsedRegression>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct FunctionRef {
+    pub defined_class: String,
+    pub method_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct FunctionChange {
+    pub function: FunctionRef,
+    ///Names of the class map fields that differ. Example: ["location", "labels"].
+    pub fields: Vec<String>,
+}
+
+/// A call from `caller` to `callee`. Calls without a recorded caller have no `caller`.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct CallEdge {
+    pub caller: Option<FunctionRef>,
+    pub callee: FunctionRef,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct EventChange {
+    ///"http_server_request", "http_client_request" or "sql_query".
+    pub kind: String,
+    ///Example: "GET /api/users" or the SQL query.
+    pub description: String,
+    pub base_count: usize,
+    pub head_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ElapsedRegression {
+    pub function: FunctionRef,
+    pub base_mean_elapsed: f64,
+    pub head_mean_elapsed: f64,
+}
+
+impl AppMapObject {
+    /// Compares this map (the base) with `head`.
+    pub fn diff(&self, head: &AppMapObject, options: &DiffOptions) -> AppMapDiff {
+        let mut diff = AppMapDiff::default();
+
+        let base_functions = class_map_functions(self);
+        let head_functions = class_map_functions(head);
+        for (key, head_function) in head_functions.iter() {
+            match base_functions.get(key) {
+                None => diff.added_functions.push(key.clone()),
+                Some(base_function) => {
+                    let fields = changed_fields(base_function, head_function);
+                    if !fields.is_empty() {
+                        diff.changed_functions.push(FunctionChange {
+                            function: key.clone(),
+                            fields,
+                        });
+                    }
+                }
+            }
+        }
+        diff.removed_functions = base_functions
+            .keys()
+            .filter(|x| !head_functions.contains_key(x))
+            .cloned()
+            .collect();
+
+        let base_edges = call_edges(self);
+        let head_edges = call_edges(head);
+        diff.added_calls = head_edges.difference(&base_edges).cloned().collect();
+        diff.removed_calls = base_edges.difference(&head_edges).cloned().collect();
+
+        let base_events = external_events(self);
+        let head_events = external_events(head);
+        let keys: BTreeSet<&(String, String)> =
+            base_events.keys().chain(head_events.keys()).collect();
+        for key in keys {
+            let base_count = base_events.get(key).copied().unwrap_or(0);
+            let head_count = head_events.get(key).copied().unwrap_or(0);
+            if base_count != head_count {
+                diff.changed_events.push(EventChange {
+                    kind: key.0.clone(),
+                    description: key.1.clone(),
+                    base_count,
+                    head_count,
+                });
+            }
+        }
+
+        let base_stats = self.stats();
+        for head_stats in head.stats().functions {
+            let Some(base_stats) = base_stats.functions.iter().find(|x| {
+                x.defined_class == head_stats.defined_class && x.method_id == head_stats.method_id
+            }) else {
+                continue;
+            };
+            if base_stats.mean_elapsed > 0.0
+                && head_stats.mean_elapsed
+                    > base_stats.mean_elapsed * (1.0 + options.elapsed_threshold)
+            {
+                diff.elapsed_regressions.push(ElapsedRegression {
+                    function: FunctionRef {
+                        defined_class: head_stats.defined_class,
+                        method_id: head_stats.method_id,
+                    },
+                    base_mean_elapsed: base_stats.mean_elapsed,
+                    head_mean_elapsed: head_stats.mean_elapsed,
+                });
+            }
+        }
+        diff
+    }
+}
+
+impl AppMapDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &AppMapDiff::default()
+    }
+}
+
+fn class_map_functions(data: &AppMapObject) -> BTreeMap<FunctionRef, &FunctionCodeObject> {
+    let mut functions = vec![];
+    for node in data.class_map.iter() {
+        collect_functions_in_tree(node, "", &mut functions);
+    }
+    functions
+        .into_iter()
+        .map(|(class, function)| {
+            let key = FunctionRef {
+                defined_class: class,
+                method_id: function.name.clone(),
+            };
+            (key, function)
+        })
+        .collect()
+}
+
+fn changed_fields(base: &FunctionCodeObject, head: &FunctionCodeObject) -> Vec<String> {
+    let mut fields = vec![];
+    if base.location != head.location {
+        fields.push("location".to_string());
+    }
+    if base.is_static != head.is_static {
+        fields.push("static".to_string());
+    }
+    if base.labels != head.labels {
+        fields.push("labels".to_string());
+    }
+    if base.comment != head.comment {
+        fields.push("comment".to_string());
+    }
+    if base.source != head.source {
+        fields.push("source".to_string());
+    }
+    fields
+}
+
+fn function_ref(call: &CallObject) -> FunctionRef {
+    FunctionRef {
+        defined_class: call.defined_class.clone(),
+        method_id: call.method_id.clone(),
+    }
+}
+
+fn call_edges(data: &AppMapObject) -> BTreeSet<CallEdge> {
+    let tree = CallTree::new(data);
+    tree.nodes
+        .iter()
+        .map(|node| CallEdge {
+            caller: node
+                .parent
+                .map(|parent| function_ref(tree.nodes[parent].call_object)),
+            callee: function_ref(node.call_object),
+        })
+        .collect()
+}
+
+const EXTERNAL_KINDS: [&str; 3] = ["http_server_request", "http_client_request", "sql_query"];
+
+/// Counts the HTTP requests and SQL queries of a map by kind and description.
+fn external_events(data: &AppMapObject) -> BTreeMap<(String, String), usize> {
+    let mut result = BTreeMap::new();
+    for event in data.events.iter() {
+        let EventObjectType::Call(call) = &event.event else {
+            continue;
+        };
+        let kind = call.type_.kind();
+        if !EXTERNAL_KINDS.contains(&kind) {
+            continue;
+        }
+        let description = call.type_.description().unwrap_or_default();
+        *result.entry((kind.to_string(), description)).or_default() += 1;
+    }
+    result
+}
+
+impl Display for FunctionRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}::{}", self.defined_class, self.method_id)
+    }
+}
+
+impl Display for CallEdge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.caller {
+            Some(caller) => write!(f, "{} -> {}", caller, self.callee),
+            None => write!(f, "<root> -> {}", self.callee),
+        }
+    }
+}
+
+impl Display for AppMapDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for x in self.added_functions.iter() {
+            writeln!(f, "+ function {}", x)?;
+        }
+        for x in self.removed_functions.iter() {
+            writeln!(f, "- function {}", x)?;
+        }
+        for x in self.changed_functions.iter() {
+            writeln!(f, "~ function {} ({})", x.function, x.fields.join(", "))?;
+        }
+        for x in self.added_calls.iter() {
+            writeln!(f, "+ call {}", x)?;
+        }
+        for x in self.removed_calls.iter() {
+            writeln!(f, "- call {}", x)?;
+        }
+        for x in self.changed_events.iter() {
+            let sign = match (x.base_count, x.head_count) {
+                (0, _) => '+',
+                (_, 0) => '-',
+                _ => '~',
+            };
+            writeln!(
+                f,
+                "{} {} {} ({} -> {})",
+                sign, x.kind, x.description, x.base_count, x.head_count
+            )?;
+        }
+        for x in self.elapsed_regressions.iter() {
+            writeln!(
+                f,
+                "! slower {} ({:.6}s -> {:.6}s)",
+                x.function, x.base_mean_elapsed, x.head_mean_elapsed
+            )?;
+        }
+        Ok(())
+    }
+}
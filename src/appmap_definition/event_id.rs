@@ -2,7 +2,7 @@ use std::ops::{Deref, DerefMut};
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct EventId(u64);
 impl Deref for EventId {
     type Target = u64;
@@ -23,7 +23,7 @@ impl From<u64> for EventId {
     }
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct ObjectId(u64);
 impl Deref for ObjectId {
     type Target = u64;
@@ -37,3 +37,9 @@ impl DerefMut for ObjectId {
         &mut self.0
     }
 }
+
+impl From<u64> for ObjectId {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::rules::{RuleSet, Severity};
+use crate::appmap_definition::*;
+use crate::redaction::mask_sql_literals;
+
+pub const N_PLUS_ONE_QUERY: &str = "n-plus-one-query";
+pub const REPEATED_HTTP_CLIENT_REQUEST: &str = "repeated-http-client-request";
+pub const REPEATED_FUNCTION_CALL: &str = "repeated-function-call";
+const PERFORMANCE: &str = "Performance";
+///Id, title and description of every rule.
+const RULES: [(&str, &str, &str); 3] = [
+    (
+        N_PLUS_ONE_QUERY,
+        "N plus 1 SQL query",
+        "The same SQL query is run many times within one call, usually once per item of a loop \
+         instead of once for all items.",
+    ),
+    (
+        REPEATED_HTTP_CLIENT_REQUEST,
+        "Repeated HTTP client request",
+        "The same HTTP request is sent many times within one call.",
+    ),
+    (
+        REPEATED_FUNCTION_CALL,
+        "Function called repeatedly",
+        "A function calls another function many times, e.g. in a loop.",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindingsOptions {
+    ///Number of times the same SQL query has to run in one call to be reported.
+    pub min_query_repeats: usize,
+    ///Number of times the same HTTP request has to be sent in one call to be reported.
+    pub min_request_repeats: usize,
+    ///Number of calls of one function by one call above which they are reported.
+    pub max_function_calls: usize,
+}
+impl Default for FindingsOptions {
+    fn default() -> Self {
+        Self {
+            min_query_repeats: 5,
+            min_request_repeats: 3,
+            max_function_calls: 10,
+        }
+    }
+}
+
+/// The contents of an `appmap-findings.json` file, in the format written by the AppMap
+/// scanner.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FindingsReport {
+    pub configuration: ScannerConfiguration,
+    ///Metadata of every scanned map, by file name.
+    pub app_map_metadata: BTreeMap<String, MetadataObject>,
+    pub findings: Vec<Finding>,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScannerConfiguration {
+    pub checks: Vec<CheckConfiguration>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CheckConfiguration {
+    pub rule: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub id: String,
+    pub scope: String,
+    pub impact_domain: String,
+    pub rule: Rule,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub impact_domain: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub app_map_file: Option<String>,
+    pub check_id: String,
+    pub rule_id: String,
+    pub rule_title: String,
+    ///The first offending call.
+    pub event: EventObject,
+    ///Identifies the finding across recordings: the rule, the offending function or query and
+    /// the function of the scope.
+    #[serde(rename = "hash_v2")]
+    pub hash_v2: String,
+    ///Locations of the calls from the offending call up to its root call.
+    pub stack: Vec<String>,
+    ///The call the offending calls happened in, the first of them if the finding merges several.
+    pub scope: EventObject,
+    pub message: String,
+    ///Spelled like the AppMap scanner does.
+    #[serde(rename = "occurranceCount")]
+    pub occurrence_count: usize,
+    ///Every offending call, including `event`.
+    pub related_events: Vec<EventObject>,
+    pub impact_domain: String,
+    ///Severity of the rule for findings of a [RuleSet]. Extension.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub severity: Option<Severity>,
+}
+
+impl FindingsReport {
+    pub fn new() -> Self {
+        let checks: Vec<Check> = RULES
+            .into_iter()
+            .map(|(id, title, description)| Check {
+                id: id.to_string(),
+                scope: "root".to_string(),
+                impact_domain: PERFORMANCE.to_string(),
+                rule: Rule {
+                    id: id.to_string(),
+                    title: title.to_string(),
+                    description: description.to_string(),
+                    impact_domain: PERFORMANCE.to_string(),
+                },
+            })
+            .collect();
+        Self {
+            configuration: ScannerConfiguration {
+                checks: checks
+                    .iter()
+                    .map(|x| CheckConfiguration { rule: x.id.clone() })
+                    .collect(),
+            },
+            app_map_metadata: BTreeMap::new(),
+            findings: vec![],
+            checks,
+        }
+    }
+
+    /// Adds the findings of `data`, read from the file `file`.
+    pub fn add(&mut self, file: &str, data: &AppMapObject, options: &FindingsOptions) {
+        self.app_map_metadata
+            .insert(file.to_string(), data.metadata.clone().unwrap_or_default());
+        self.findings
+            .extend(data.findings(options).into_iter().map(|x| Finding {
+                app_map_file: Some(file.to_string()),
+                ..x
+            }));
+    }
+
+    /// Adds a check for every rule of `rules`, which have to be added before their findings.
+    pub fn add_rules(&mut self, rules: &RuleSet) {
+        for rule in rules.rules.iter() {
+            self.configuration.checks.push(CheckConfiguration {
+                rule: rule.id.clone(),
+            });
+            self.checks.push(Check {
+                id: rule.id.clone(),
+                scope: "root".to_string(),
+                impact_domain: rule.impact_domain().to_string(),
+                rule: Rule {
+                    id: rule.id.clone(),
+                    title: rule.title().to_string(),
+                    description: rule.description.clone().unwrap_or_default(),
+                    impact_domain: rule.impact_domain().to_string(),
+                },
+            });
+        }
+    }
+
+    /// Adds the violations of `rules` by `data`, read from the file `file`.
+    pub fn add_rule_findings(&mut self, file: &str, data: &AppMapObject, rules: &RuleSet) {
+        self.app_map_metadata
+            .insert(file.to_string(), data.metadata.clone().unwrap_or_default());
+        self.findings
+            .extend(data.check_rules(rules).into_iter().map(|x| Finding {
+                app_map_file: Some(file.to_string()),
+                ..x
+            }));
+    }
+}
+impl Default for FindingsReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppMapObject {
+    /// Scans the calls for performance problems:
+    ///
+    /// * [N_PLUS_ONE_QUERY]: the same SQL query, ignoring its literals, runs at least
+    ///   `min_query_repeats` times directly in one call.
+    /// * [REPEATED_HTTP_CLIENT_REQUEST]: the same HTTP client request is sent at least
+    ///   `min_request_repeats` times directly in one call.
+    /// * [REPEATED_FUNCTION_CALL]: a call calls the same function more than
+    ///   `max_function_calls` times.
+    ///
+    /// The scope of a finding is the call the repeated calls happened in, or the first of them if
+    /// they are root calls. Findings with the same `hash_v2`, i.e. of several calls of the same
+    /// function, are reported once with the sum of their occurrences.
+    pub fn findings(&self, options: &FindingsOptions) -> Vec<Finding> {
+        let tree = CallTree::new(self);
+        let mut queries: BTreeMap<(Option<usize>, String), Vec<usize>> = BTreeMap::new();
+        let mut requests: BTreeMap<(Option<usize>, String), Vec<usize>> = BTreeMap::new();
+        let mut calls: BTreeMap<(usize, String), Vec<usize>> = BTreeMap::new();
+        for (index, node) in tree.nodes.iter().enumerate() {
+            match &node.call_object.type_ {
+                CallObjectType::SqlQuery(x) => queries
+                    .entry((node.parent, normalize_sql(&x.sql_query.sql)))
+                    .or_default()
+                    .push(index),
+                CallObjectType::HttpClientRequest(x) => {
+                    let request = &x.http_client_request;
+                    requests
+                        .entry((
+                            node.parent,
+                            format!("{} {}", request.request_method, request.url),
+                        ))
+                        .or_default()
+                        .push(index)
+                }
+                CallObjectType::Normal | CallObjectType::Function => {
+                    if let Some(parent) = node.parent {
+                        calls
+                            .entry((parent, function_name(&tree, index)))
+                            .or_default()
+                            .push(index);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut repeated = RepeatedCalls::default();
+        for ((parent, sql), indexes) in queries {
+            if indexes.len() >= options.min_query_repeats {
+                let scope = parent.unwrap_or(indexes[0]);
+                repeated.add(&tree, N_PLUS_ONE_QUERY, sql, scope, indexes);
+            }
+        }
+        for ((parent, request), indexes) in requests {
+            if indexes.len() >= options.min_request_repeats {
+                let scope = parent.unwrap_or(indexes[0]);
+                repeated.add(&tree, REPEATED_HTTP_CLIENT_REQUEST, request, scope, indexes);
+            }
+        }
+        for ((scope, function), indexes) in calls {
+            if indexes.len() > options.max_function_calls {
+                repeated.add(&tree, REPEATED_FUNCTION_CALL, function, scope, indexes);
+            }
+        }
+        repeated.calls.iter().map(|x| finding(&tree, x)).collect()
+    }
+}
+
+/// Calls repeated in one or more calls of the same scope function.
+#[derive(Debug)]
+struct Repeated {
+    rule: &'static str,
+    ///The query, request or function which was repeated.
+    subject: String,
+    hash: u64,
+    scopes: Vec<usize>,
+    indexes: Vec<usize>,
+}
+
+/// The repeated calls of a map, merged by hash in the order they were found.
+#[derive(Debug, Default)]
+struct RepeatedCalls {
+    calls: Vec<Repeated>,
+}
+
+impl RepeatedCalls {
+    fn add(
+        &mut self,
+        tree: &CallTree<'_>,
+        rule: &'static str,
+        subject: String,
+        scope: usize,
+        indexes: Vec<usize>,
+    ) {
+        let hash = fnv1a(&[rule, &subject, &function_name(tree, scope)].join("\n"));
+        match self.calls.iter_mut().find(|x| x.hash == hash) {
+            Some(repeated) => {
+                repeated.scopes.push(scope);
+                repeated.indexes.extend(indexes);
+            }
+            None => self.calls.push(Repeated {
+                rule,
+                subject,
+                hash,
+                scopes: vec![scope],
+                indexes,
+            }),
+        }
+    }
+}
+
+fn finding(tree: &CallTree<'_>, repeated: &Repeated) -> Finding {
+    let first = repeated.indexes[0];
+    let scope = repeated.scopes[0];
+    let count = repeated.indexes.len();
+    let scope_name = match repeated.scopes.len() {
+        1 => function_name(tree, scope),
+        scopes => format!("{} calls of {}", scopes, function_name(tree, scope)),
+    };
+    let message = match repeated.rule {
+        N_PLUS_ONE_QUERY => format!("{} ran {} times in {}", repeated.subject, count, scope_name),
+        REPEATED_HTTP_CLIENT_REQUEST => format!(
+            "{} was sent {} times in {}",
+            repeated.subject, count, scope_name
+        ),
+        _ => format!(
+            "{} was called {} times by {}",
+            repeated.subject, count, scope_name
+        ),
+    };
+    Finding {
+        app_map_file: None,
+        check_id: repeated.rule.to_string(),
+        rule_id: repeated.rule.to_string(),
+        rule_title: RULES
+            .iter()
+            .find(|(id, _, _)| *id == repeated.rule)
+            .map(|(_, title, _)| title.to_string())
+            .unwrap_or_default(),
+        event: tree.nodes[first].call.clone(),
+        hash_v2: format!("{:016x}", repeated.hash),
+        stack: tree
+            .stack(first)
+            .into_iter()
+            .rev()
+            .map(|x| location(tree, x))
+            .collect(),
+        scope: tree.nodes[scope].call.clone(),
+        message,
+        occurrence_count: count,
+        related_events: repeated
+            .indexes
+            .iter()
+            .map(|x| tree.nodes[*x].call.clone())
+            .collect(),
+        impact_domain: PERFORMANCE.to_string(),
+        severity: None,
+    }
+}
+
+pub(crate) fn function_name(tree: &CallTree<'_>, index: usize) -> String {
+    let call = tree.nodes[index].call_object;
+    format!("{}::{}", call.defined_class, call.method_id)
+}
+
+/// "path:lineno" of the call, or its function if it has no path.
+pub(crate) fn location(tree: &CallTree<'_>, index: usize) -> String {
+    let call = tree.nodes[index].call_object;
+    match (call.path.as_ref(), call.lineno) {
+        (Some(path), Some(lineno)) => format!("{}:{}", path.display(), lineno),
+        (Some(path), None) => path.display().to_string(),
+        _ => function_name(tree, index),
+    }
+}
+
+/// The query with its literals replaced by `?` and its whitespace collapsed.
+fn normalize_sql(sql: &str) -> String {
+    mask_sql_literals(sql)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 64 bit FNV-1a, which unlike the hasher of the standard library is stable across versions.
+pub(crate) fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(severity) = self.severity {
+            write!(f, "[{}] ", severity)?;
+        }
+        match self.app_map_file.as_ref() {
+            Some(file) => write!(f, "{}: {} ({})", self.rule_title, self.message, file),
+            None => write!(f, "{}: {}", self.rule_title, self.message),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::appmap_definition::*;
+use crate::node_functions::merge_into_tree;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    ///Orders the merged events by their `timestamp` instead of appending one map after the
+    /// other. Events without a timestamp keep their position relative to the previous event of
+    /// the same map.
+    pub order_by_timestamp: bool,
+}
+
+impl AppMapObject {
+    /// Combines several maps into one.
+    ///
+    /// Event ids are renumbered starting at 1 and `parent_id`s are rewritten to match. Threads
+    /// of different maps that use the same id get a new id that is not used by any map. Class
+    /// maps are merged by name and metadata is combined field by field.
+    pub fn merge(maps: impl IntoIterator<Item = AppMapObject>, options: &MergeOptions) -> Self {
+        let maps: Vec<AppMapObject> = maps.into_iter().collect();
+        let mut result = AppMapObject {
+            version: maps
+                .first()
+                .map(|x| x.version.clone())
+                .unwrap_or_else(|| "1.12".to_string()),
+            metadata: None,
+            class_map: vec![],
+            events: vec![],
+            event_updates: None,
+        };
+
+        let mut next_thread_id = maps
+            .iter()
+            .flat_map(|x| x.events.iter().map(|x| x.thread_id))
+            .max()
+            .unwrap_or(0)
+            .saturating_add(1);
+        let mut used_thread_ids = HashSet::new();
+
+        // (map index, sort key, event)
+        let mut events: Vec<(usize, f64, EventObject)> = vec![];
+        let mut event_updates: Vec<(usize, EventObject)> = vec![];
+        for (map_index, map) in maps.into_iter().enumerate() {
+            let mut thread_ids = HashMap::new();
+            for event in map.events.iter() {
+                thread_ids.entry(event.thread_id).or_insert_with(|| {
+                    if used_thread_ids.insert(event.thread_id) {
+                        event.thread_id
+                    } else {
+                        next_thread_id += 1;
+                        next_thread_id - 1
+                    }
+                });
+            }
+
+            let mut last_timestamp = f64::MIN;
+            for mut event in map.events.into_iter() {
+                event.thread_id = thread_ids[&event.thread_id];
+                let sort_key = if options.order_by_timestamp {
+                    last_timestamp = event.timestamp.unwrap_or(last_timestamp);
+                    last_timestamp
+                } else {
+                    map_index as f64
+                };
+                events.push((map_index, sort_key, event));
+            }
+            for (_, mut event) in map.event_updates.into_iter().flatten() {
+                event.thread_id = *thread_ids.get(&event.thread_id).unwrap_or(&event.thread_id);
+                event_updates.push((map_index, event));
+            }
+
+            for node in map.class_map {
+                merge_into_tree(&mut result.class_map, node);
+            }
+            if let Some(metadata) = map.metadata {
+                match result.metadata.as_mut() {
+                    Some(existing) => existing.merge(metadata),
+                    None => result.metadata = Some(metadata),
+                }
+            }
+        }
+        events.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut new_ids: HashMap<(usize, EventId), EventId> = HashMap::new();
+        for (index, (map_index, _, event)) in events.iter_mut().enumerate() {
+            let id = EventId::from(index as u64 + 1);
+            new_ids.insert((*map_index, event.id), id);
+            event.id = id;
+        }
+        let remap = |map_index: usize, event: &mut EventObject| match &mut event.event {
+            EventObjectType::Call(call) => {
+                if let Some(caused_by) = call.caused_by.as_mut() {
+                    // causes in another map or missing from their map are dropped
+                    *caused_by = caused_by
+                        .iter()
+                        .filter_map(|x| new_ids.get(&(map_index, *x)).copied())
+                        .collect();
+                    if caused_by.is_empty() {
+                        call.caused_by = None;
+                    }
+                }
+            }
+            EventObjectType::Return(ret) => {
+                if let Some(parent_id) = new_ids.get(&(map_index, ret.parent_id)) {
+                    ret.parent_id = *parent_id;
+                }
+            }
+        };
+        for (map_index, _, event) in events.iter_mut() {
+            remap(*map_index, event);
+        }
+        for (map_index, mut event) in event_updates {
+            let Some(id) = new_ids.get(&(map_index, event.id)) else {
+                continue;
+            };
+            event.id = *id;
+            remap(map_index, &mut event);
+            result
+                .event_updates
+                .get_or_insert_with(HashMap::new)
+                .insert(**id as u32, event);
+        }
+        result.events = events.into_iter().map(|(_, _, event)| event).collect();
+        result
+    }
+}
+
+impl MetadataObject {
+    /// Fills the fields that are not set yet from `other` and combines the list fields.
+    /// A failed test status wins over a succeeded one.
+    pub fn merge(&mut self, other: MetadataObject) {
+        fn merge_list<T: PartialEq>(target: &mut Option<Vec<T>>, other: Option<Vec<T>>) {
+            for value in other.into_iter().flatten() {
+                let list = target.get_or_insert_with(Vec::new);
+                if !list.contains(&value) {
+                    list.push(value);
+                }
+            }
+        }
+        merge_list(&mut self.labels, other.labels);
+        merge_list(&mut self.frameworks, other.frameworks);
+        if other.test_status == Some(TestStatus::Failed) {
+            self.test_status = other.test_status;
+        }
+        self.name = self.name.take().or(other.name);
+        self.app = self.app.take().or(other.app);
+        self.language = self.language.take().or(other.language);
+        self.client = self.client.take().or(other.client);
+        self.recorder = self.recorder.take().or(other.recorder);
+        self.recording = self.recording.take().or(other.recording);
+        self.test_status = self.test_status.take().or(other.test_status);
+        self.test_failure = self.test_failure.take().or(other.test_failure);
+        self.exception = self.exception.take().or(other.exception);
+        if let Some(dropped_events) = other.dropped_events {
+            *self.dropped_events.get_or_insert(0) += dropped_events;
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+use crate::node_functions::{collect_functions_in_tree, retain_functions_in_tree};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneOptions {
+    ///Functions that never call anything are removed entirely once they are called more often
+    /// than this.
+    pub max_leaf_calls: Option<usize>,
+    ///Runs of consecutive sibling calls with an identical call structure are cut down to this
+    /// many calls.
+    pub max_repeated_subtrees: Option<usize>,
+    ///Target size of the map serialized as compact JSON, in bytes. The most frequently called
+    /// functions are removed until the map fits.
+    pub max_size: Option<usize>,
+}
+
+impl AppMapObject {
+    /// Removes calls from the map according to `options`.
+    ///
+    /// A call is always removed together with its return and everything it called, so the
+    /// remaining call/return pairs stay balanced, and the remaining calls lose the causes that
+    /// were removed. Functions that are no longer referenced by any
+    /// call are removed from the class map afterwards.
+    pub fn prune(&mut self, options: &PruneOptions) -> Result<(), Box<dyn Error>> {
+        let tree = CallTree::new(self);
+        let mut removed = vec![false; tree.nodes.len()];
+
+        if let Some(max_leaf_calls) = options.max_leaf_calls {
+            remove_frequent_leaf_functions(&tree, &mut removed, max_leaf_calls);
+        }
+        if let Some(max_repeated) = options.max_repeated_subtrees {
+            remove_repeated_subtrees(&tree, &mut removed, max_repeated);
+        }
+        if let Some(max_size) = options.max_size {
+            remove_until_size(self, &tree, &mut removed, max_size)?;
+        }
+
+        let removed_events = removed_event_indices(self, &tree, &removed);
+
+        let mut index = 0;
+        self.events.retain(|_| {
+            let keep = !removed_events.contains(&index);
+            index += 1;
+            keep
+        });
+        let remaining_ids: HashSet<u64> = self.events.iter().map(|x| *x.id).collect();
+        if let Some(event_updates) = self.event_updates.as_mut() {
+            event_updates.retain(|id, _| remaining_ids.contains(&(*id as u64)));
+        }
+        for event in self.events.iter_mut() {
+            if let EventObjectType::Call(call) = &mut event.event {
+                if let Some(caused_by) = call.caused_by.as_mut() {
+                    caused_by.retain(|x| remaining_ids.contains(&**x));
+                    if caused_by.is_empty() {
+                        call.caused_by = None;
+                    }
+                }
+            }
+        }
+        self.retain_called_functions();
+        Ok(())
+    }
+
+    /// Drops every function from the class map that is not the target of a call event, and every
+    /// package or class that is left empty by that.
+    pub fn retain_called_functions(&mut self) {
+        let called: HashSet<(&str, &str)> = self
+            .events
+            .iter()
+            .filter_map(|x| match &x.event {
+                EventObjectType::Call(call) => {
+                    Some((call.defined_class.as_str(), call.method_id.as_str()))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut class_map = std::mem::take(&mut self.class_map);
+        class_map.retain_mut(|node| {
+            retain_functions_in_tree(node, "", &|class, method| called.contains(&(class, method)))
+        });
+        self.class_map = class_map;
+    }
+}
+
+fn function_key<'a>(tree: &CallTree<'a>, index: usize) -> (&'a str, &'a str) {
+    let call = tree.nodes[index].call_object;
+    (&call.defined_class, &call.method_id)
+}
+
+fn remove_subtree(tree: &CallTree, removed: &mut [bool], index: usize) {
+    for x in tree.descendants(index) {
+        removed[x] = true;
+    }
+}
+
+fn remove_frequent_leaf_functions(tree: &CallTree, removed: &mut [bool], max_leaf_calls: usize) {
+    let mut calls: HashMap<(&str, &str), (usize, bool)> = HashMap::new();
+    for (index, node) in tree.nodes.iter().enumerate() {
+        let entry = calls.entry(function_key(tree, index)).or_insert((0, true));
+        entry.0 += 1;
+        entry.1 &= node.children.is_empty();
+    }
+    for index in 0..tree.nodes.len() {
+        let (count, is_leaf) = calls[&function_key(tree, index)];
+        if is_leaf && count > max_leaf_calls {
+            removed[index] = true;
+        }
+    }
+}
+
+fn remove_repeated_subtrees(tree: &CallTree, removed: &mut [bool], max_repeated: usize) {
+    let mut signatures = vec![0u64; tree.nodes.len()];
+    // children always come after their parent, so walking backwards sees them first
+    for index in (0..tree.nodes.len()).rev() {
+        let mut hasher = DefaultHasher::new();
+        function_key(tree, index).hash(&mut hasher);
+        for child in tree.nodes[index].children.iter() {
+            signatures[*child].hash(&mut hasher);
+        }
+        signatures[index] = hasher.finish();
+    }
+
+    let mut sibling_groups: Vec<&[usize]> = tree
+        .nodes
+        .iter()
+        .map(|node| node.children.as_slice())
+        .collect();
+    let mut roots_by_thread: HashMap<u32, Vec<usize>> = HashMap::new();
+    for root in tree.roots.iter() {
+        roots_by_thread
+            .entry(tree.nodes[*root].call.thread_id)
+            .or_default()
+            .push(*root);
+    }
+    sibling_groups.extend(roots_by_thread.values().map(|x| x.as_slice()));
+
+    for siblings in sibling_groups {
+        let mut run = 0;
+        let mut previous = None;
+        for sibling in siblings {
+            if previous == Some(signatures[*sibling]) {
+                run += 1;
+            } else {
+                run = 1;
+                previous = Some(signatures[*sibling]);
+            }
+            if run > max_repeated {
+                remove_subtree(tree, removed, *sibling);
+            }
+        }
+    }
+}
+
+/// Removes the calls of the most frequently called function, together with everything below
+/// them, until the map fits into `max_size` bytes.
+///
+/// The size is tracked as calls are removed instead of serializing the map again. A function
+/// leaves the class map once none of its calls is left; classes and packages that end up
+/// empty are still counted, so the pruned map may be slightly smaller than `max_size`.
+fn remove_until_size(
+    data: &AppMapObject,
+    tree: &CallTree,
+    removed: &mut [bool],
+    max_size: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut event_sizes = Vec::with_capacity(data.events.len());
+    for event in data.events.iter() {
+        // +1 for the separating comma
+        event_sizes.push(serde_json::to_vec(event)?.len() + 1);
+    }
+    // size of the map with one comma too many if there are events
+    let mut size = serde_json::to_vec(&AppMapObject {
+        version: data.version.clone(),
+        metadata: data.metadata.clone(),
+        class_map: data.class_map.clone(),
+        events: vec![],
+        event_updates: data.event_updates.clone(),
+    })?
+    .len();
+    let removed_events = removed_event_indices(data, tree, removed);
+    let mut events = data.events.len() - removed_events.len();
+    size += event_sizes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !removed_events.contains(index))
+        .map(|(_, size)| size)
+        .sum::<usize>();
+
+    let mut calls: HashMap<(&str, &str), Vec<usize>> = HashMap::new();
+    for index in (0..tree.nodes.len()).filter(|x| !removed[*x]) {
+        calls
+            .entry(function_key(tree, index))
+            .or_default()
+            .push(index);
+    }
+    let mut class_map_functions = vec![];
+    for node in data.class_map.iter() {
+        collect_functions_in_tree(node, "", &mut class_map_functions);
+    }
+    let mut function_sizes: HashMap<(String, &str), usize> = HashMap::new();
+    for (class, function) in class_map_functions {
+        let function_size =
+            serde_json::to_vec(&CodeObjectType::Function(function.clone()))?.len() + 1;
+        if calls.contains_key(&(class.as_str(), function.name.as_str())) {
+            function_sizes.insert((class, &function.name), function_size);
+        } else {
+            size = size.saturating_sub(function_size);
+        }
+    }
+
+    let mut counts: HashMap<(&str, &str), usize> =
+        calls.iter().map(|(key, x)| (*key, x.len())).collect();
+    let mut most_called: BinaryHeap<(usize, (&str, &str))> =
+        counts.iter().map(|(key, count)| (*count, *key)).collect();
+    while size - events.min(1) > max_size {
+        let Some((count, key)) = most_called.pop() else {
+            return Ok(());
+        };
+        // counts only go down, so an outdated entry is put back with its current count
+        if counts[&key] != count {
+            if counts[&key] > 0 {
+                most_called.push((counts[&key], key));
+            }
+            continue;
+        }
+        for index in calls[&key].iter() {
+            for x in tree.descendants(*index) {
+                if removed[x] {
+                    continue;
+                }
+                removed[x] = true;
+                let node = &tree.nodes[x];
+                size -= event_sizes[node.call_index];
+                events -= 1;
+                if let Some(return_index) = node.return_index {
+                    size -= event_sizes[return_index];
+                    events -= 1;
+                }
+                let function = function_key(tree, x);
+                let count = counts.get_mut(&function).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    let class_map_key = (function.0.to_string(), function.1);
+                    size = size
+                        .saturating_sub(function_sizes.get(&class_map_key).copied().unwrap_or(0));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Indices in `events` of the calls marked as removed, their returns and all returns that do
+/// not belong to a call at all.
+fn removed_event_indices(data: &AppMapObject, tree: &CallTree, removed: &[bool]) -> HashSet<usize> {
+    let mut result = HashSet::new();
+    let mut matched_returns = HashSet::new();
+    for (index, node) in tree.nodes.iter().enumerate() {
+        if let Some(return_index) = node.return_index {
+            matched_returns.insert(return_index);
+        }
+        if removed[index] {
+            result.insert(node.call_index);
+            result.extend(node.return_index);
+        }
+    }
+    for (index, event) in data.events.iter().enumerate() {
+        if matches!(event.event, EventObjectType::Return(_)) && !matched_returns.contains(&index) {
+            result.insert(index);
+        }
+    }
+    result
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::findings::{fnv1a, function_name, location, Finding};
+use crate::appmap_definition::*;
+use crate::filter::matches;
+use crate::node_functions::collect_functions_in_tree;
+
+const SECURITY: &str = "Security";
+
+/// Rules loaded from a YAML file like `appmap-rules.yml`:
+///
+/// ```yaml
+/// rules:
+///   - id: secret-in-log
+///     title: Secret used while logging
+///     severity: error
+///     match:
+///       labels: [secret]
+///     ancestor:
+///       labels: [log]
+///   - id: slow-query
+///     title: Slow SQL query
+///     match:
+///       type: sql_query
+///       min_elapsed: 0.5
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RuleSet {
+    pub rules: Vec<RuleConfig>,
+}
+
+/// A policy for calls. A call violates the rule if it matches `match` and every relation given
+/// holds for it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RuleConfig {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+    ///Impact domain of the findings, "Security" by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub impact_domain: Option<String>,
+    ///The calls the rule applies to.
+    #[serde(rename = "match")]
+    pub match_: CallMatcher,
+    ///The direct caller has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub parent: Option<CallMatcher>,
+    ///Some caller up to the root call has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub ancestor: Option<CallMatcher>,
+    ///No caller up to the root call may match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub not_ancestor: Option<CallMatcher>,
+    ///Some direct callee has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub child: Option<CallMatcher>,
+    ///Some call below it has to match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub descendant: Option<CallMatcher>,
+}
+
+/// Conditions on a single call, all of which have to hold. An empty matcher matches every call.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CallMatcher {
+    ///Labels the function of the call needs to have in the class map, all of them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub labels: Vec<String>,
+    ///Module or end of the path of the function, like the entries of `exclude` in `appmap.yml`.
+    /// Example: "my_crate::db" or "Repository::save".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub function: Option<String>,
+    ///Kind of call, see [CallObjectType::kind]. Example: "sql_query".
+    #[serde(rename = "type")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub type_: Option<String>,
+    ///Elapsed time in seconds the call has to take at least.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub min_elapsed: Option<f64>,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Describes the calls matched. Example: "a sql_query call of my_app::db labeled secret".
+impl Display for CallMatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a ")?;
+        if let Some(type_) = self.type_.as_ref() {
+            write!(f, "{} ", type_)?;
+        }
+        write!(f, "call")?;
+        if let Some(function) = self.function.as_ref() {
+            write!(f, " of {}", function)?;
+        }
+        if !self.labels.is_empty() {
+            write!(f, " labeled {}", self.labels.join(", "))?;
+        }
+        if let Some(min_elapsed) = self.min_elapsed {
+            write!(f, " taking at least {}s", min_elapsed)?;
+        }
+        Ok(())
+    }
+}
+
+impl RuleSet {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let rules: Self = serde_yaml::from_reader(file)?;
+        for rule in rules.rules.iter() {
+            if rule.id.is_empty() {
+                return Err("a rule has an empty id".into());
+            }
+            if rules.rules.iter().filter(|x| x.id == rule.id).count() > 1 {
+                return Err(format!("rule {} is defined more than once", rule.id).into());
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl RuleConfig {
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(&self.id)
+    }
+
+    pub fn impact_domain(&self) -> &str {
+        self.impact_domain.as_deref().unwrap_or(SECURITY)
+    }
+}
+
+/// Labels of every function of the class map, by class and method.
+struct FunctionLabels<'a> {
+    labels: HashMap<String, HashMap<&'a str, &'a [String]>>,
+}
+
+impl<'a> FunctionLabels<'a> {
+    fn new(data: &'a AppMapObject) -> Self {
+        let mut functions = vec![];
+        for node in data.class_map.iter() {
+            collect_functions_in_tree(node, "", &mut functions);
+        }
+        let mut labels: HashMap<String, HashMap<&str, &[String]>> = HashMap::new();
+        for (class, function) in functions {
+            if let Some(function_labels) = function.labels.as_deref() {
+                labels
+                    .entry(class)
+                    .or_default()
+                    .insert(&function.name, function_labels);
+            }
+        }
+        Self { labels }
+    }
+
+    fn get(&self, call: &CallObject) -> &[String] {
+        self.labels
+            .get(&call.defined_class)
+            .and_then(|x| x.get(call.method_id.as_str()))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl CallMatcher {
+    fn matches(&self, tree: &CallTree<'_>, labels: &FunctionLabels<'_>, index: usize) -> bool {
+        let call = tree.nodes[index].call_object;
+        if let Some(type_) = self.type_.as_ref() {
+            if call.type_.kind() != type_ {
+                return false;
+            }
+        }
+        if let Some(pattern) = self.function.as_ref() {
+            if !matches(pattern, &call.defined_class, &call.method_id) {
+                return false;
+            }
+        }
+        if let Some(min_elapsed) = self.min_elapsed {
+            if tree.elapsed(index).is_none_or(|x| x < min_elapsed) {
+                return false;
+            }
+        }
+        let call_labels = labels.get(call);
+        self.labels.iter().all(|x| call_labels.contains(x))
+    }
+}
+
+impl AppMapObject {
+    /// Evaluates `rules` against every call and returns a finding for every violation, with the
+    /// severity of the rule. The scope of a finding is the call that fulfilled the `parent`,
+    /// `ancestor`, `child` or `descendant` relation of the rule, if any, else the root call.
+    /// Violations with the same `hash_v2` are merged into the first finding, which counts them
+    /// and lists all their calls as `related_events`.
+    pub fn check_rules(&self, rules: &RuleSet) -> Vec<Finding> {
+        let tree = CallTree::new(self);
+        let labels = FunctionLabels::new(self);
+        let mut findings: Vec<Finding> = vec![];
+        for rule in rules.rules.iter() {
+            for index in 0..tree.nodes.len() {
+                if !rule.match_.matches(&tree, &labels, index) {
+                    continue;
+                }
+                let Some(finding) = check_relations(&tree, &labels, rule, index) else {
+                    continue;
+                };
+                match findings.iter_mut().find(|x| x.hash_v2 == finding.hash_v2) {
+                    Some(first) => {
+                        first.occurrence_count += finding.occurrence_count;
+                        for event in finding.related_events {
+                            if !first.related_events.iter().any(|x| x.id == event.id) {
+                                first.related_events.push(event);
+                            }
+                        }
+                    }
+                    None => findings.push(finding),
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// The finding for the call at `index`, which matches the rule, if all relations of the rule
+/// hold for it.
+fn check_relations(
+    tree: &CallTree<'_>,
+    labels: &FunctionLabels<'_>,
+    rule: &RuleConfig,
+    index: usize,
+) -> Option<Finding> {
+    let matching = |matcher: &CallMatcher, mut indexes: Box<dyn Iterator<Item = usize> + '_>| {
+        indexes.find(|x| matcher.matches(tree, labels, *x))
+    };
+    let node = &tree.nodes[index];
+    let mut stack = tree.stack(index);
+    stack.pop();
+    let mut clauses = vec![];
+    let mut related = None;
+
+    if let Some(elapsed) = rule.match_.min_elapsed.and(tree.elapsed(index)) {
+        clauses.push(format!("took {:.3}s", elapsed));
+    }
+    if let Some(matcher) = rule.parent.as_ref() {
+        let parent = matching(matcher, Box::new(node.parent.into_iter()))?;
+        clauses.push(format!("was called by {}", function_name(tree, parent)));
+        related.get_or_insert(parent);
+    }
+    if let Some(matcher) = rule.ancestor.as_ref() {
+        let ancestor = matching(matcher, Box::new(stack.iter().rev().copied()))?;
+        clauses.push(format!(
+            "was called under {}",
+            function_name(tree, ancestor)
+        ));
+        related.get_or_insert(ancestor);
+    }
+    if let Some(matcher) = rule.not_ancestor.as_ref() {
+        if matching(matcher, Box::new(stack.iter().copied())).is_some() {
+            return None;
+        }
+        clauses.push(format!("was not called under {}", matcher));
+    }
+    if let Some(matcher) = rule.child.as_ref() {
+        let child = matching(matcher, Box::new(node.children.iter().copied()))?;
+        clauses.push(format!("called {}", subject(tree, child)));
+        related.get_or_insert(child);
+    }
+    if let Some(matcher) = rule.descendant.as_ref() {
+        let descendants = tree.descendants(index).into_iter().filter(|x| *x != index);
+        let descendant = matching(matcher, Box::new(descendants))?;
+        clauses.push(format!("led to {}", subject(tree, descendant)));
+        related.get_or_insert(descendant);
+    }
+    if clauses.is_empty() {
+        clauses.push("was called".to_string());
+    }
+
+    let scope = related.unwrap_or(stack.first().copied().unwrap_or(index));
+    let mut related_events = vec![node.call.clone()];
+    related_events.extend(related.map(|x| tree.nodes[x].call.clone()));
+    let hash = fnv1a(
+        &[
+            rule.id.as_str(),
+            &subject(tree, index),
+            &related.map(|x| function_name(tree, x)).unwrap_or_default(),
+        ]
+        .join("\n"),
+    );
+    Some(Finding {
+        app_map_file: None,
+        check_id: rule.id.clone(),
+        rule_id: rule.id.clone(),
+        rule_title: rule.title().to_string(),
+        event: node.call.clone(),
+        hash_v2: format!("{:016x}", hash),
+        stack: tree
+            .stack(index)
+            .into_iter()
+            .rev()
+            .map(|x| location(tree, x))
+            .collect(),
+        scope: tree.nodes[scope].call.clone(),
+        message: format!("{} {}", subject(tree, index), clauses.join(" and ")),
+        occurrence_count: 1,
+        related_events,
+        impact_domain: rule.impact_domain().to_string(),
+        severity: Some(rule.severity),
+    })
+}
+
+/// The SQL query or HTTP request of the call, or its function.
+fn subject(tree: &CallTree<'_>, index: usize) -> String {
+    tree.nodes[index]
+        .call_object
+        .type_
+        .description()
+        .unwrap_or_else(|| function_name(tree, index))
+}
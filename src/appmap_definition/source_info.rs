@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::Span;
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{Attribute, Expr, ExprLit, Lit, Meta, Signature, Visibility};
+use tracing::warn;
+
+use crate::appmap_definition::*;
+use crate::node_functions::for_each_function_in_tree_mut;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceOptions {
+    ///Set `comment` to the doc comment of the function.
+    pub comment: bool,
+    ///Set `source` to the verbatim code of the function, without its doc comment.
+    pub source: bool,
+    ///Point `location` at the line of the `fn` keyword instead of the line the span was created
+    /// at, which is the line of the `#[instrument]` attribute.
+    pub fix_location: bool,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        Self {
+            comment: true,
+            source: true,
+            fix_location: true,
+        }
+    }
+}
+
+/// The functions of every source file read so far, so each file is only parsed once even if it
+/// is needed for many functions or maps.
+#[derive(Debug, Default)]
+pub struct SourceCache {
+    root: PathBuf,
+    ///None if the file could not be read or parsed.
+    files: HashMap<PathBuf, Option<Vec<FunctionSource>>>,
+}
+
+#[derive(Debug, Clone)]
+struct FunctionSource {
+    name: String,
+    ///Line of the first attribute or doc comment.
+    first_line: usize,
+    ///Line of the visibility or `fn` keyword.
+    fn_line: usize,
+    last_line: usize,
+    comment: Option<String>,
+    source: String,
+}
+
+impl SourceCache {
+    /// Relative paths in the `location` of functions are resolved against `root`, which should
+    /// be the directory cargo was run in when the map was recorded.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            files: HashMap::new(),
+        }
+    }
+
+    fn functions(&mut self, path: &str) -> Option<&[FunctionSource]> {
+        let path = self.root.join(path);
+        self.files
+            .entry(path)
+            .or_insert_with_key(|path| parse_functions(path))
+            .as_deref()
+    }
+
+    /// The innermost function named `name` around `line` of the file at `path`.
+    fn find(&mut self, path: &str, line: usize, name: &str) -> Option<&FunctionSource> {
+        self.functions(path)?
+            .iter()
+            .filter(|x| x.name == name && x.first_line <= line && line <= x.last_line)
+            .max_by_key(|x| x.first_line)
+    }
+}
+
+fn parse_functions(path: &Path) -> Option<Vec<FunctionSource>> {
+    let text = std::fs::read_to_string(path).ok()?;
+    let file = match syn::parse_file(&text) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("could not parse {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mut collector = FunctionCollector {
+        lines: text.lines().collect(),
+        functions: vec![],
+    };
+    collector.visit_file(&file);
+    Some(collector.functions)
+}
+
+struct FunctionCollector<'a> {
+    lines: Vec<&'a str>,
+    functions: Vec<FunctionSource>,
+}
+
+impl FunctionCollector<'_> {
+    fn add(&mut self, attrs: &[Attribute], vis: &Visibility, sig: &Signature, span: Span) {
+        let fn_line = match vis {
+            Visibility::Inherited => sig.span().start().line,
+            _ => vis.span().start().line,
+        };
+        let last_line = span.end().line;
+        let source = self
+            .lines
+            .get(fn_line.saturating_sub(1)..last_line.min(self.lines.len()))
+            .unwrap_or_default()
+            .join("\n");
+        self.functions.push(FunctionSource {
+            name: sig.ident.to_string(),
+            first_line: span.start().line,
+            fn_line,
+            last_line,
+            comment: doc_comment(attrs),
+            source,
+        });
+    }
+}
+
+impl<'ast> Visit<'ast> for FunctionCollector<'_> {
+    fn visit_item_fn(&mut self, i: &'ast syn::ItemFn) {
+        self.add(&i.attrs, &i.vis, &i.sig, i.span());
+        syn::visit::visit_item_fn(self, i);
+    }
+    fn visit_impl_item_fn(&mut self, i: &'ast syn::ImplItemFn) {
+        self.add(&i.attrs, &i.vis, &i.sig, i.span());
+        syn::visit::visit_impl_item_fn(self, i);
+    }
+    fn visit_trait_item_fn(&mut self, i: &'ast syn::TraitItemFn) {
+        if i.default.is_some() {
+            self.add(&i.attrs, &Visibility::Inherited, &i.sig, i.span());
+        }
+        syn::visit::visit_trait_item_fn(self, i);
+    }
+}
+
+fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|x| x.path().is_ident("doc"))
+        .filter_map(|x| match &x.meta {
+            Meta::NameValue(meta) => match &meta.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|x| x.strip_prefix(' ').map(String::from).unwrap_or(x))
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+impl AppMapObject {
+    /// Fills in the `comment` and `source` of the functions in the class map from their source
+    /// files. Functions whose file can not be found or parsed are left unchanged.
+    pub fn add_source_info(&mut self, cache: &mut SourceCache, options: &SourceOptions) {
+        for node in self.class_map.iter_mut() {
+            for_each_function_in_tree_mut(node, &mut |function| {
+                let Some((path, line)) = function
+                    .location
+                    .as_deref()
+                    .and_then(|x| x.rsplit_once(':'))
+                else {
+                    return;
+                };
+                let Ok(line) = line.parse() else {
+                    return;
+                };
+                let path = path.to_string();
+                let Some(source) = cache.find(&path, line, &function.name) else {
+                    return;
+                };
+                if options.fix_location {
+                    function.location = Some(format!("{}:{}", path, source.fn_line));
+                }
+                if options.comment {
+                    function.comment = source.comment.clone();
+                }
+                if options.source {
+                    function.source = Some(source.source.clone());
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+use crate::node_functions::collect_functions_in_tree;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AppMapStats {
+    ///Sorted by total elapsed time, then by number of calls (both descending).
+    pub functions: Vec<FunctionStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct FunctionStats {
+    pub defined_class: String,
+    pub method_id: String,
+    pub calls: usize,
+    ///Sum of the elapsed time of all calls, in seconds.
+    pub total_elapsed: f64,
+    ///Sum of the elapsed time of all calls without the time spent in recorded child calls.
+    pub self_elapsed: f64,
+    pub mean_elapsed: f64,
+    pub p95_elapsed: f64,
+    ///Deepest nesting this function was called at. Root calls have a depth of 0.
+    pub max_depth: usize,
+    pub threads: Vec<ThreadStats>,
+    ///Calls that were counted but not recorded because the function reached its call cap.
+    pub unrecorded_calls: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ThreadStats {
+    pub thread_id: u32,
+    pub calls: usize,
+    pub total_elapsed: f64,
+    pub self_elapsed: f64,
+}
+
+impl AppMapObject {
+    /// Computes call counts and timings for every function in the class map and every function
+    /// called in `events`.
+    pub fn stats(&self) -> AppMapStats {
+        let mut functions: BTreeMap<(String, String), (FunctionStats, Vec<f64>)> = BTreeMap::new();
+
+        let mut class_map_functions = vec![];
+        for node in self.class_map.iter() {
+            collect_functions_in_tree(node, "", &mut class_map_functions);
+        }
+        for (class, function) in class_map_functions {
+            let (stats, _) = functions
+                .entry((class.clone(), function.name.clone()))
+                .or_insert_with(|| (new_function_stats(&class, &function.name), vec![]));
+            stats.unrecorded_calls += function.unrecorded_calls.unwrap_or(0);
+        }
+
+        let tree = CallTree::new(self);
+        for (index, node) in tree.nodes.iter().enumerate() {
+            let class = &node.call_object.defined_class;
+            let method = &node.call_object.method_id;
+            let (stats, samples) = functions
+                .entry((class.clone(), method.clone()))
+                .or_insert_with(|| (new_function_stats(class, method), vec![]));
+
+            let elapsed = tree.elapsed(index);
+            let self_elapsed = tree.self_elapsed(index);
+            stats.calls += 1;
+            stats.total_elapsed += elapsed.unwrap_or(0.0);
+            stats.self_elapsed += self_elapsed.unwrap_or(0.0);
+            stats.max_depth = stats.max_depth.max(node.depth);
+            if let Some(elapsed) = elapsed {
+                samples.push(elapsed);
+            }
+
+            let thread_id = node.call.thread_id;
+            let thread = match stats.threads.iter_mut().find(|x| x.thread_id == thread_id) {
+                Some(thread) => thread,
+                None => {
+                    stats.threads.push(ThreadStats {
+                        thread_id,
+                        ..Default::default()
+                    });
+                    stats.threads.last_mut().expect("We just pushed it")
+                }
+            };
+            thread.calls += 1;
+            thread.total_elapsed += elapsed.unwrap_or(0.0);
+            thread.self_elapsed += self_elapsed.unwrap_or(0.0);
+        }
+
+        let mut functions: Vec<FunctionStats> = functions
+            .into_values()
+            .map(|(mut stats, mut samples)| {
+                if !samples.is_empty() {
+                    stats.mean_elapsed = samples.iter().sum::<f64>() / samples.len() as f64;
+                    samples.sort_by(f64::total_cmp);
+                    stats.p95_elapsed = percentile(&samples, 0.95);
+                }
+                stats.threads.sort_by_key(|x| x.thread_id);
+                stats
+            })
+            .collect();
+        functions.sort_by(|a, b| {
+            b.total_elapsed
+                .total_cmp(&a.total_elapsed)
+                .then(b.calls.cmp(&a.calls))
+        });
+        AppMapStats { functions }
+    }
+}
+
+fn new_function_stats(class: &str, method: &str) -> FunctionStats {
+    FunctionStats {
+        defined_class: class.to_string(),
+        method_id: method.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Nearest-rank percentile of already sorted samples.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl Display for AppMapStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = self
+            .functions
+            .iter()
+            .map(|x| format!("{}::{}", x.defined_class, x.method_id))
+            .collect();
+        let width = names.iter().map(|x| x.len()).max().unwrap_or(0).max(8);
+        writeln!(
+            f,
+            "{:<width$} {:>8} {:>12} {:>12} {:>12} {:>12} {:>6} {:>8}",
+            "function", "calls", "total", "self", "mean", "p95", "depth", "threads",
+        )?;
+        for (name, stats) in names.iter().zip(self.functions.iter()) {
+            writeln!(
+                f,
+                "{:<width$} {:>8} {:>12.6} {:>12.6} {:>12.6} {:>12.6} {:>6} {:>8}",
+                name,
+                stats.calls,
+                stats.total_elapsed,
+                stats.self_elapsed,
+                stats.mean_elapsed,
+                stats.p95_elapsed,
+                stats.max_depth,
+                stats.threads.len(),
+            )?;
+            if stats.threads.len() > 1 {
+                for thread in stats.threads.iter() {
+                    writeln!(
+                        f,
+                        "{:<width$} {:>8} {:>12.6} {:>12.6}",
+                        format!("  thread {}", thread.thread_id),
+                        thread.calls,
+                        thread.total_elapsed,
+                        thread.self_elapsed,
+                    )?;
+                }
+            }
+            if stats.unrecorded_calls > 0 {
+                writeln!(
+                    f,
+                    "{:<width$} {:>8}",
+                    "  not recorded", stats.unrecorded_calls,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::DEFAULT_APPMAP_DIR;
+
+/// The contents of an `appmap.yml` file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AppMapConfig {
+    ///Name of the recorded app. Example: "appmap_tracing_test".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub language: Option<String>,
+    ///Directory the AppMaps are written to. Defaults to "maps/tmp".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub appmap_dir: Option<PathBuf>,
+    ///Packages to record. Every span is recorded if there are none.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub packages: Vec<PackageConfig>,
+    ///Targets and functions that are never recorded. See [crate::filter::SpanFilter].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    ///Least important level of the recorded spans, e.g. "info" to ignore debug and trace spans.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub level: Option<String>,
+    ///Labels given to the recorded functions. See [crate::labels::LabelRules].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub functions: Vec<FunctionConfig>,
+    ///What is redacted from the recorded values. See [crate::redaction::Redactor].
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    ///How many calls are recorded. See [crate::sampling::Sampler].
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PackageConfig {
+    ///Required target prefix of the package. Example: "my_crate::db".
+    pub path: String,
+    ///Targets and functions of the package that are not recorded.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FunctionConfig {
+    ///Required pattern of the functions to label, matched like the entries of `exclude`.
+    /// Example: "my_crate::auth::login".
+    pub name: String,
+    ///Required labels of the matching functions. Example: ["security.authentication"].
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SamplingConfig {
+    ///Record one in every `rate` root spans together with all spans below them. Example: 100.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub rate: Option<u64>,
+    ///Number of calls recorded per function. Further calls are only counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub max_calls_per_function: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RedactionConfig {
+    ///Parameters and headers whose name contains one of these (ignoring case) are redacted.
+    #[serde(default = "default_redacted_names")]
+    pub names: Vec<String>,
+    ///Regular expressions of secrets which are redacted from every recorded value.
+    #[serde(default = "default_redacted_values")]
+    pub values: Vec<String>,
+    ///Replace the literals of recorded SQL queries with `?`.
+    #[serde(default = "default_true")]
+    pub sql: bool,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            names: default_redacted_names(),
+            values: default_redacted_values(),
+            sql: true,
+        }
+    }
+}
+
+fn default_redacted_names() -> Vec<String> {
+    [
+        "authorization",
+        "password",
+        "passwd",
+        "secret",
+        "token",
+        "cookie",
+        "api_key",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_redacted_values() -> Vec<String> {
+    [
+        // JSON web tokens
+        r"eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*",
+        // AWS access key ids
+        r"\b(AKIA|ASIA)[0-9A-Z]{16}\b",
+        // bearer and basic credentials
+        r"(?i)\b(bearer|basic) [A-Za-z0-9._~+/=-]+",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl AppMapConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+
+    /// Loads the `appmap.yml` of the crate being built or run (found through
+    /// `CARGO_MANIFEST_DIR`) or of the current directory. Returns the default configuration if
+    /// there is none.
+    pub fn find() -> Result<Self, Box<dyn Error>> {
+        let directory = std::env::var_os("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_default();
+        let path = directory.join("appmap.yml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut config = Self::load(path)?;
+        if let Some(appmap_dir) = config.appmap_dir.as_mut() {
+            if appmap_dir.is_relative() {
+                *appmap_dir = directory.join(&appmap_dir);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn appmap_dir(&self) -> PathBuf {
+        self.appmap_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_APPMAP_DIR))
+    }
+}
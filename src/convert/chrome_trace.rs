@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+use crate::parameters::{json_parameter, parameter};
+use crate::AppMap;
+
+/// A trace in the Chrome Trace Event Format, as read by `chrome://tracing` and Perfetto.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ChromeTrace {
+    #[serde(rename = "traceEvents")]
+    pub trace_events: Vec<TraceEvent>,
+    #[serde(rename = "displayTimeUnit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub display_time_unit: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct TraceEvent {
+    ///Not required for end events.
+    #[serde(default)]
+    pub name: String,
+    ///Comma separated list of categories.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub cat: Option<String>,
+    ///Phase of the event. "B" and "E" begin and end a duration, "X" is a complete event.
+    pub ph: String,
+    ///Timestamp in microseconds.
+    #[serde(default)]
+    pub ts: f64,
+    ///Duration in microseconds, only used by complete events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub dur: Option<f64>,
+    #[serde(default)]
+    pub pid: u64,
+    #[serde(default)]
+    pub tid: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub args: Option<BTreeMap<String, Value>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChromeTracePhase {
+    ///One "X" event per call.
+    #[default]
+    Complete,
+    ///A "B" event at the start and an "E" event at the end of every call.
+    BeginEnd,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChromeTraceOptions {
+    pub phase: ChromeTracePhase,
+    ///Process id written to every event.
+    pub pid: u64,
+}
+
+/// Converts the calls of `data` into trace events. Calls are laid out with
+/// [CallTree::timings] and carry their event id, parameters, receiver and return value as args.
+pub fn to_chrome_trace(data: &AppMapObject, options: &ChromeTraceOptions) -> ChromeTrace {
+    let tree = CallTree::new(data);
+    let timings = tree.timings();
+    let mut trace = ChromeTrace {
+        trace_events: vec![],
+        display_time_unit: Some("ms".to_string()),
+    };
+    let mut pending = tree
+        .roots
+        .iter()
+        .rev()
+        .map(|x| (*x, false))
+        .collect::<Vec<_>>();
+    while let Some((index, finished)) = pending.pop() {
+        let node = &tree.nodes[index];
+        let (start, end) = timings[index];
+        let name = node
+            .call_object
+            .type_
+            .description()
+            .unwrap_or_else(|| node.call_object.method_id.clone());
+        let event = TraceEvent {
+            name,
+            cat: Some(node.call_object.defined_class.clone()),
+            ph: String::new(),
+            ts: start * 1_000_000.0,
+            dur: None,
+            pid: options.pid,
+            tid: node.call.thread_id as u64,
+            args: None,
+        };
+        match (options.phase, finished) {
+            (ChromeTracePhase::Complete, _) => {
+                trace.trace_events.push(TraceEvent {
+                    ph: "X".to_string(),
+                    dur: Some((end - start) * 1_000_000.0),
+                    args: Some(args(node.call, node.call_object, node.return_object)),
+                    ..event
+                });
+            }
+            (ChromeTracePhase::BeginEnd, false) => {
+                trace.trace_events.push(TraceEvent {
+                    ph: "B".to_string(),
+                    args: Some(args(node.call, node.call_object, None)),
+                    ..event
+                });
+                pending.push((index, true));
+            }
+            (ChromeTracePhase::BeginEnd, true) => {
+                let return_args = node.return_object.map(return_args);
+                trace.trace_events.push(TraceEvent {
+                    ph: "E".to_string(),
+                    ts: end * 1_000_000.0,
+                    args: return_args.filter(|x| !x.is_empty()),
+                    ..event
+                });
+                continue;
+            }
+        }
+        pending.extend(node.children.iter().rev().map(|x| (*x, false)));
+    }
+    trace
+}
+
+fn args(
+    event: &EventObject,
+    call: &CallObject,
+    ret: Option<&ReturnObject>,
+) -> BTreeMap<String, Value> {
+    let mut args = BTreeMap::new();
+    args.insert("event_id".to_string(), Value::from(*event.id));
+    args.insert("type".to_string(), Value::from(call.type_.kind()));
+    if let Some(receiver) = call.receiver.as_ref() {
+        args.insert("receiver".to_string(), parameter_value(receiver));
+    }
+    for (index, parameter) in call.parameters.iter().flatten().enumerate() {
+        let name = parameter
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("arg{}", index));
+        args.insert(name, parameter_value(parameter));
+    }
+    if let Some(ret) = ret {
+        args.extend(return_args(ret));
+    }
+    args
+}
+
+fn return_args(ret: &ReturnObject) -> BTreeMap<String, Value> {
+    let mut args = BTreeMap::new();
+    if let ReturnObjectType::Function(function) = &ret.data {
+        if let Some(return_value) = function.return_value.as_ref() {
+            args.insert("return_value".to_string(), parameter_value(return_value));
+        }
+    }
+    args
+}
+
+fn parameter_value(parameter: &ParameterObject) -> Value {
+    Value::from(format!("{}: {}", parameter.class, parameter.value))
+}
+
+/// Reads a trace in the Chrome Trace Event Format, see [from_chrome_trace]. Both the object
+/// format and the array format are read, including an array which was not closed because the
+/// traced process did not finish, as written by `tracing-chrome`.
+pub fn read_chrome_trace(path: impl AsRef<Path>) -> Result<AppMapObject, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)?;
+    let trace = match serde_json::from_str::<ChromeTrace>(&content) {
+        Ok(trace) => trace,
+        Err(_) => {
+            let content = content.trim_end().trim_end_matches(',');
+            let events = match serde_json::from_str(content) {
+                Ok(events) => events,
+                Err(_) if content.starts_with('[') => {
+                    serde_json::from_str(&format!("{}]", content))?
+                }
+                Err(e) => return Err(e.into()),
+            };
+            ChromeTrace {
+                trace_events: events,
+                display_time_unit: None,
+            }
+        }
+    };
+    Ok(from_chrome_trace(&trace))
+}
+
+/// A call of the trace, from a complete event or a matching pair of begin and end events.
+#[derive(Debug)]
+struct TracedCall<'a> {
+    begin: &'a TraceEvent,
+    ///Arguments of the end event.
+    end_args: Option<&'a BTreeMap<String, Value>>,
+    ///Microseconds, none if the call did not end.
+    end: Option<f64>,
+}
+
+/// Converts the complete ("X") and begin and end ("B" and "E") events of `trace` into calls.
+/// Other events are ignored.
+///
+/// The class of a call is the first category of its event, or the path of its name if it has
+/// no category, e.g. `my_crate::db` for an event named `my_crate::db::query`. Threads are told
+/// apart by their process and thread id and numbered in the order they appear, starting at 1.
+/// Calls are nested by their start and end on each thread, an end event ends the last call
+/// begun on its thread. Arguments become parameters; `return_value` and `receiver` arguments,
+/// and the arguments of a trace written by [to_chrome_trace], are read back as they were
+/// written.
+pub fn from_chrome_trace(trace: &ChromeTrace) -> AppMapObject {
+    let mut calls: Vec<TracedCall<'_>> = vec![];
+    let mut begun: BTreeMap<(u64, u64), Vec<TracedCall<'_>>> = BTreeMap::new();
+    let mut threads: HashMap<(u64, u64), u32> = HashMap::new();
+    for event in trace.trace_events.iter() {
+        if matches!(event.ph.as_str(), "X" | "B" | "E") {
+            let next_id = threads.len() as u32 + 1;
+            threads.entry(thread(event)).or_insert(next_id);
+        }
+        match event.ph.as_str() {
+            "X" => calls.push(TracedCall {
+                begin: event,
+                end_args: None,
+                end: Some(event.ts + event.dur.unwrap_or(0.0)),
+            }),
+            "B" => begun.entry(thread(event)).or_default().push(TracedCall {
+                begin: event,
+                end_args: None,
+                end: None,
+            }),
+            "E" => {
+                if let Some(mut call) = begun.get_mut(&thread(event)).and_then(|x| x.pop()) {
+                    call.end_args = event.args.as_ref();
+                    call.end = Some(event.ts);
+                    calls.push(call);
+                }
+            }
+            _ => {}
+        }
+    }
+    calls.extend(begun.into_values().flatten());
+
+    // the index of each call in the order the calls start on their thread, with the longest
+    // call first
+    let end = |x: &TracedCall<'_>| x.end.unwrap_or(f64::INFINITY);
+    let mut order: Vec<usize> = (0..calls.len()).collect();
+    order.sort_by(|a, b| {
+        let (a, b) = (&calls[*a], &calls[*b]);
+        thread(a.begin)
+            .cmp(&thread(b.begin))
+            .then(a.begin.ts.total_cmp(&b.begin.ts))
+            .then(end(b).total_cmp(&end(a)))
+    });
+    // the call (true) and return (false) events of every call, with their time in microseconds
+    let mut events: Vec<(f64, usize, bool)> = vec![];
+    let mut stack: Vec<(usize, f64)> = vec![];
+    for (position, index) in order.iter().enumerate() {
+        let call = &calls[*index];
+        while let Some((open, open_end)) = stack.last() {
+            if *open_end > call.begin.ts && thread(calls[*open].begin) == thread(call.begin) {
+                break;
+            }
+            events.push((*open_end, *open, false));
+            stack.pop();
+        }
+        // a call ending after its parent is cut off at the end of the parent
+        let call_end = stack
+            .last()
+            .map_or(end(call), |(_, parent_end)| end(call).min(*parent_end));
+        events.push((call.begin.ts, *index, true));
+        stack.push((*index, call_end));
+        if position + 1 == order.len() {
+            while let Some((open, open_end)) = stack.pop() {
+                events.push((open_end, open, false));
+            }
+        }
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut app_map = AppMap::new();
+    let mut event_ids: HashMap<usize, EventId> = HashMap::new();
+    for (time, index, is_call) in events {
+        let call = &calls[index];
+        let timestamp = Some(time / 1_000_000.0).filter(|x| x.is_finite());
+        let thread_id = threads[&thread(call.begin)];
+        let appmap = written_by_appmap(call.begin.args.as_ref());
+        if is_call {
+            let (class, method) = class_and_method(call.begin);
+            let (receiver, parameters, _) = arguments(call.begin.args.as_ref(), appmap);
+            let event_id = app_map.add_function_call_event(
+                thread_id,
+                class,
+                method,
+                None,
+                None,
+                receiver.is_none(),
+                Some(parameters).filter(|x| !x.is_empty()),
+            );
+            if let Some(EventObjectType::Call(call)) =
+                app_map.event_mut(event_id).map(|x| &mut x.event)
+            {
+                call.receiver = receiver;
+            }
+            app_map.set_timestamp(event_id, timestamp);
+            event_ids.insert(index, event_id);
+        } else if let Some(event_id) = event_ids.get(&index) {
+            let (_, _, begin_return) = arguments(call.begin.args.as_ref(), appmap);
+            let (_, _, end_return) = arguments(call.end_args, appmap);
+            let data = match end_return.or(begin_return) {
+                Some(return_value) => ReturnObjectType::Function(FunctionReturnObject {
+                    return_value: Some(return_value),
+                    exceptions: None,
+                }),
+                None => ReturnObjectType::Normal,
+            };
+            let elapsed = call.end.map(|end| (end - call.begin.ts) / 1_000_000.0);
+            let return_id = app_map.add_function_return_event(thread_id, *event_id, elapsed, data);
+            app_map.set_timestamp(return_id, timestamp);
+        }
+    }
+
+    let mut data = app_map.data;
+    data.metadata = Some(MetadataObject {
+        recorder: Some(RecorderObject {
+            name: "chrome-trace".to_string(),
+            type_: Some("process".to_string()),
+        }),
+        ..Default::default()
+    });
+    data
+}
+
+fn thread(event: &TraceEvent) -> (u64, u64) {
+    (event.pid, event.tid)
+}
+
+fn class_and_method(event: &TraceEvent) -> (String, String) {
+    let category = event
+        .cat
+        .as_deref()
+        .and_then(|x| x.split(',').next())
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty());
+    match (category, event.name.rsplit_once("::")) {
+        (Some(category), _) => (category.to_string(), event.name.clone()),
+        (None, Some((class, method))) => (class.to_string(), method.to_string()),
+        (None, None) => ("trace".to_string(), event.name.clone()),
+    }
+}
+
+/// Whether `args` of a begin or complete event were written by [to_chrome_trace].
+fn written_by_appmap(args: Option<&BTreeMap<String, Value>>) -> bool {
+    args.is_some_and(|x| x.contains_key("event_id") && x.contains_key("type"))
+}
+
+/// The receiver, parameters and return value given by the arguments `args` of an event, which
+/// are "class: value" strings if they were `written_by_appmap`.
+fn arguments(
+    args: Option<&BTreeMap<String, Value>>,
+    written_by_appmap: bool,
+) -> (
+    Option<ParameterObject>,
+    Vec<ParameterObject>,
+    Option<ParameterObject>,
+) {
+    let Some(args) = args else {
+        return (None, vec![], None);
+    };
+    let parameter = |name: Option<&str>, value: &Value| match value {
+        Value::String(x) if written_by_appmap => match x.split_once(": ") {
+            Some((class, value)) => ParameterObject {
+                name: name.map(|x| x.to_string()),
+                ..parameter("", class, value.to_string())
+            },
+            None => json_parameter(name, value),
+        },
+        _ => json_parameter(name, value),
+    };
+    let mut receiver = None;
+    let mut parameters = vec![];
+    let mut return_value = None;
+    for (name, value) in args.iter() {
+        match name.as_str() {
+            "event_id" | "type" if written_by_appmap => {}
+            "receiver" => receiver = Some(parameter(None, value)),
+            "return_value" => return_value = Some(parameter(None, value)),
+            _ => parameters.push(parameter(Some(name), value)),
+        }
+    }
+    (receiver, parameters, return_value)
+}
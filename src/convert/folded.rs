@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FoldedWeight {
+    ///Self time of the calls in microseconds.
+    #[default]
+    SelfTime,
+    ///Number of calls.
+    Calls,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoldedOptions {
+    pub weight: FoldedWeight,
+    ///Adds a "thread <id>" frame at the bottom of every stack.
+    pub per_thread: bool,
+}
+
+/// Converts the calls of `data` into folded stacks as read by `inferno-flamegraph` and
+/// `flamegraph.pl`: one line per distinct stack with its frames separated by `;`, followed by
+/// the weight. Stacks with a weight of 0 are left out.
+pub fn to_folded(data: &AppMapObject, options: &FoldedOptions) -> String {
+    let tree = CallTree::new(data);
+    let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+    for index in 0..tree.nodes.len() {
+        let weight = match options.weight {
+            FoldedWeight::SelfTime => {
+                let self_elapsed = tree.self_elapsed(index).unwrap_or(0.0);
+                (self_elapsed * 1_000_000.0).round() as u64
+            }
+            FoldedWeight::Calls => 1,
+        };
+        if weight == 0 {
+            continue;
+        }
+        let mut frames: Vec<String> = vec![];
+        if options.per_thread {
+            frames.push(format!("thread {}", tree.nodes[index].call.thread_id));
+        }
+        frames.extend(
+            tree.stack(index)
+                .into_iter()
+                .map(|x| frame(tree.nodes[x].call_object)),
+        );
+        *stacks.entry(frames.join(";")).or_default() += weight;
+    }
+    stacks
+        .into_iter()
+        .map(|(stack, weight)| format!("{} {}\n", stack, weight))
+        .collect()
+}
+
+fn frame(call: &CallObject) -> String {
+    let name = match call.type_.description() {
+        Some(description) => format!("{} {}", call.type_.kind(), description),
+        None => format!("{}::{}", call.defined_class, call.method_id),
+    };
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(';', ",")
+}
@@ -0,0 +1,5 @@
+pub mod chrome_trace;
+pub mod folded;
+pub mod otlp;
+pub mod sequence_diagram;
+pub mod tracing_json;
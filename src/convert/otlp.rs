@@ -0,0 +1,439 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+use crate::appmap_definition::call_tree::{CallNode, CallTree};
+use crate::appmap_definition::*;
+
+pub const SPAN_KIND_INTERNAL: u32 = 1;
+pub const SPAN_KIND_SERVER: u32 = 2;
+pub const SPAN_KIND_CLIENT: u32 = 3;
+pub const STATUS_CODE_ERROR: u32 = 2;
+
+/// Traces in the JSON encoding of the OpenTelemetry protocol (OTLP), as read by the `otlp`
+/// receiver and the `otlpjsonfile` receiver of the OpenTelemetry Collector.
+///
+/// Trace and span ids are hex strings, 64 bit integers are decimal strings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpTraces {
+    pub resource_spans: Vec<ResourceSpans>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSpans {
+    pub resource: Resource,
+    pub scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Resource {
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ScopeSpans {
+    pub scope: InstrumentationScope,
+    pub spans: Vec<OtlpSpan>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct InstrumentationScope {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OtlpSpan {
+    ///32 hex digits.
+    pub trace_id: String,
+    ///16 hex digits.
+    pub span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    ///One of the `SPAN_KIND_*` constants.
+    pub kind: u32,
+    pub start_time_unix_nano: String,
+    pub end_time_unix_nano: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub events: Vec<SpanEvent>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub links: Vec<SpanLink>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub status: Option<Status>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanEvent {
+    pub time_unix_nano: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpanLink {
+    pub trace_id: String,
+    pub span_id: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Status {
+    ///0 is unset, 1 is ok and 2 is [STATUS_CODE_ERROR].
+    pub code: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: AnyValue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnyValue {
+    StringValue(String),
+    BoolValue(bool),
+    ///A 64 bit integer as decimal string.
+    IntValue(String),
+    DoubleValue(f64),
+    ArrayValue(ArrayValue),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ArrayValue {
+    pub values: Vec<AnyValue>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OtlpOptions {
+    ///`service.name` of the resource. Defaults to the app or name of the map's metadata.
+    pub service_name: Option<String>,
+    ///Mixed into every trace and span id, so the spans of different maps do not share ids.
+    /// Defaults to a hash of the metadata and the first event of the map.
+    pub seed: Option<u64>,
+}
+
+/// Converts the calls of `data` into OTLP spans of one resource.
+///
+/// Every root call starts a trace and every call is a span, with ids derived from the ids of
+/// their call events and the [OtlpOptions::seed]. Calls are laid out with [CallTree::timings].
+/// Parameters, the receiver and the return value become attributes, HTTP and SQL calls get the
+/// attributes of the OpenTelemetry semantic conventions, and calls which returned an exception
+/// or an HTTP error get an error status. The `caused_by` events of a call become links.
+pub fn to_otlp(data: &AppMapObject, options: &OtlpOptions) -> OtlpTraces {
+    let tree = CallTree::new(data);
+    let timings = tree.timings();
+    let origin = tree
+        .nodes
+        .iter()
+        .filter_map(|node| node.call.timestamp)
+        .reduce(f64::min)
+        .unwrap_or(0.0);
+    // whole seconds are kept apart so the nanoseconds do not exceed the precision of f64
+    let to_nanos = |seconds: f64| {
+        let nanos = ((origin.fract() + seconds) * 1e9).round() as u64;
+        (origin.trunc() as u64 * 1_000_000_000 + nanos).to_string()
+    };
+
+    // parents come before their children
+    let mut trace_roots = Vec::with_capacity(tree.nodes.len());
+    let mut indexes = HashMap::new();
+    for (index, node) in tree.nodes.iter().enumerate() {
+        let root = node.parent.map(|x| trace_roots[x]).unwrap_or(index);
+        trace_roots.push(root);
+        indexes.insert(node.call.id, index);
+    }
+    let seed = options.seed.unwrap_or_else(|| default_seed(data));
+    let span_id = |id: EventId| format!("{:016x}", mix(seed, *id));
+    let trace_id = |index: usize| {
+        let root = tree.nodes[trace_roots[index]].call.id;
+        format!("{:016x}{:016x}", seed, mix(!seed, *root))
+    };
+
+    let mut spans = vec![];
+    for (index, node) in tree.nodes.iter().enumerate() {
+        let call = node.call_object;
+        let (start, end) = timings[index];
+        let mut span = OtlpSpan {
+            trace_id: trace_id(index),
+            span_id: span_id(node.call.id),
+            parent_span_id: node.parent.map(|x| span_id(tree.nodes[x].call.id)),
+            name: call
+                .type_
+                .description()
+                .unwrap_or_else(|| call.method_id.clone()),
+            kind: SPAN_KIND_INTERNAL,
+            start_time_unix_nano: to_nanos(start),
+            end_time_unix_nano: to_nanos(end),
+            attributes: attributes(node),
+            events: vec![],
+            links: vec![],
+            status: None,
+        };
+        add_call_type(&mut span, &call.type_);
+        for cause in call.caused_by.iter().flatten() {
+            if let Some(cause_index) = indexes.get(cause) {
+                span.links.push(SpanLink {
+                    trace_id: trace_id(*cause_index),
+                    span_id: span_id(*cause),
+                });
+            }
+        }
+        let exceptions = match node.return_object.map(|x| &x.data) {
+            Some(ReturnObjectType::Exception(x)) => &x.exceptions[..],
+            Some(ReturnObjectType::Function(x)) => x.exceptions.as_deref().unwrap_or_default(),
+            _ => &[],
+        };
+        if let Some(exception) = exceptions.first() {
+            span.status = Some(Status {
+                code: STATUS_CODE_ERROR,
+                message: Some(format!("{}: {}", exception.class, exception.message)),
+            });
+        }
+        for exception in exceptions {
+            span.events.push(SpanEvent {
+                time_unix_nano: to_nanos(end),
+                name: "exception".to_string(),
+                attributes: vec![
+                    string_attribute("exception.type", &exception.class),
+                    string_attribute("exception.message", &exception.message),
+                ],
+            });
+        }
+        spans.push(span);
+    }
+
+    let metadata = data.metadata.as_ref();
+    let service_name = options
+        .service_name
+        .clone()
+        .or_else(|| metadata.and_then(|x| x.app.clone()))
+        .or_else(|| metadata.and_then(|x| x.name.clone()))
+        .unwrap_or_else(|| "unknown_service".to_string());
+    let mut resource = vec![string_attribute("service.name", &service_name)];
+    if let Some(language) = metadata.and_then(|x| x.language.as_ref()) {
+        resource.push(string_attribute("telemetry.sdk.language", &language.name));
+    }
+    OtlpTraces {
+        resource_spans: vec![ResourceSpans {
+            resource: Resource {
+                attributes: resource,
+            },
+            scope_spans: vec![ScopeSpans {
+                scope: InstrumentationScope {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                },
+                spans,
+            }],
+        }],
+    }
+}
+
+/// A hash of the metadata and the first event of `data`.
+fn default_seed(data: &AppMapObject) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&data.metadata)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    if let Some(event) = data.events.first() {
+        serde_json::to_string(event)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+    }
+    data.events.len().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Mixes `seed` into `id`. Different ids stay different for the same seed, as the finalizer of
+/// SplitMix64 is a bijection.
+fn mix(seed: u64, id: u64) -> u64 {
+    let mut x = seed ^ id;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn attributes(node: &CallNode<'_>) -> Vec<KeyValue> {
+    let call = node.call_object;
+    let mut attributes = vec![
+        string_attribute("code.namespace", &call.defined_class),
+        string_attribute("code.function", &call.method_id),
+    ];
+    if let Some(path) = call.path.as_ref() {
+        attributes.push(string_attribute("code.filepath", &path.to_string_lossy()));
+    }
+    if let Some(lineno) = call.lineno {
+        attributes.push(int_attribute("code.lineno", lineno as u64));
+    }
+    attributes.push(int_attribute("thread.id", node.call.thread_id as u64));
+    if let Some(receiver) = call.receiver.as_ref() {
+        attributes.push(parameter_attribute("receiver", receiver));
+    }
+    for (index, parameter) in call.parameters.iter().flatten().enumerate() {
+        let name = parameter
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("arg{}", index));
+        attributes.push(parameter_attribute(&name, parameter));
+    }
+    if let Some(ReturnObjectType::Function(function)) = node.return_object.map(|x| &x.data) {
+        if let Some(return_value) = function.return_value.as_ref() {
+            attributes.push(parameter_attribute("return_value", return_value));
+        }
+    }
+    attributes
+}
+
+/// Sets the kind and adds the semantic convention attributes of HTTP and SQL calls.
+fn add_call_type(span: &mut OtlpSpan, call_type: &CallObjectType) {
+    let attributes = &mut span.attributes;
+    match call_type {
+        CallObjectType::Normal | CallObjectType::Function => {}
+        CallObjectType::HttpServerRequest(x) => {
+            let request = &x.http_server_request;
+            span.kind = SPAN_KIND_SERVER;
+            attributes.push(string_attribute(
+                "http.request.method",
+                &request.request_method,
+            ));
+            attributes.push(string_attribute("url.path", &request.path_info));
+            if let Some(route) = request.normalized_path_info.as_ref() {
+                attributes.push(string_attribute("http.route", route));
+            }
+            if let Some((name, version)) = request.protocol.as_ref().and_then(|x| x.split_once('/'))
+            {
+                attributes.push(string_attribute(
+                    "network.protocol.name",
+                    &name.to_lowercase(),
+                ));
+                attributes.push(string_attribute("network.protocol.version", version));
+            }
+            add_headers(attributes, "http.request.header", request.headers.as_ref());
+        }
+        CallObjectType::HttpClientRequest(x) => {
+            let request = &x.http_client_request;
+            span.kind = SPAN_KIND_CLIENT;
+            attributes.push(string_attribute(
+                "http.request.method",
+                &request.request_method,
+            ));
+            attributes.push(string_attribute("url.full", &request.url));
+            add_headers(attributes, "http.request.header", request.headers.as_ref());
+        }
+        CallObjectType::HttpServerResponse(x) => {
+            add_response(span, &x.http_server_response, 500);
+        }
+        CallObjectType::HttpClientResponse(x) => {
+            span.kind = SPAN_KIND_CLIENT;
+            add_response(span, &x.http_client_response, 400);
+        }
+        CallObjectType::SqlQuery(x) => {
+            let query = &x.sql_query;
+            span.kind = SPAN_KIND_CLIENT;
+            attributes.push(string_attribute("db.system", &query.database_type));
+            attributes.push(string_attribute("db.query.text", &query.sql));
+        }
+        CallObjectType::Message(x) => {
+            for (index, parameter) in x.message.iter().enumerate() {
+                let name = parameter
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("message{}", index));
+                attributes.push(parameter_attribute(&name, parameter));
+            }
+        }
+    }
+}
+
+/// Adds the status code and headers of a response, which is an error from `error_status` on.
+fn add_response(span: &mut OtlpSpan, response: &HttpResponseObject, error_status: u16) {
+    span.attributes.push(int_attribute(
+        "http.response.status_code",
+        response.status as u64,
+    ));
+    add_headers(
+        &mut span.attributes,
+        "http.response.header",
+        response.headers.as_ref(),
+    );
+    if response.status >= error_status {
+        span.status = Some(Status {
+            code: STATUS_CODE_ERROR,
+            message: None,
+        });
+    }
+}
+
+fn add_headers(
+    attributes: &mut Vec<KeyValue>,
+    prefix: &str,
+    headers: Option<&BTreeMap<String, String>>,
+) {
+    for (name, value) in headers.into_iter().flatten() {
+        attributes.push(KeyValue {
+            key: format!("{}.{}", prefix, name.to_lowercase()),
+            value: AnyValue::ArrayValue(ArrayValue {
+                values: vec![AnyValue::StringValue(value.clone())],
+            }),
+        });
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: AnyValue::StringValue(value.to_string()),
+    }
+}
+
+fn int_attribute(key: &str, value: u64) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: AnyValue::IntValue(value.to_string()),
+    }
+}
+
+/// Numbers and booleans recorded by the layer keep their type, everything else is a string.
+fn parameter_attribute(key: &str, parameter: &ParameterObject) -> KeyValue {
+    let value = match parameter.class.as_str() {
+        "i64" | "u64" | "i32" | "u32" | "usize" | "isize" => parameter
+            .value
+            .parse::<i64>()
+            .ok()
+            .map(|x| AnyValue::IntValue(x.to_string())),
+        "f64" | "f32" => parameter
+            .value
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite())
+            .map(AnyValue::DoubleValue),
+        "bool" => parameter.value.parse().ok().map(AnyValue::BoolValue),
+        _ => None,
+    };
+    KeyValue {
+        key: key.to_string(),
+        value: value.unwrap_or_else(|| AnyValue::StringValue(parameter.value.clone())),
+    }
+}
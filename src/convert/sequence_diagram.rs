@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+
+use crate::appmap_definition::call_tree::CallTree;
+use crate::appmap_definition::*;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SequenceDiagram {
+    pub actors: Vec<Actor>,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor {
+    pub name: String,
+    pub kind: ActorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActorKind {
+    ///Whoever made the calls that have no recorded caller.
+    Caller,
+    Package,
+    Class,
+    ///The server side of recorded HTTP server requests.
+    HttpServer,
+    ///A remote service called through HTTP client requests, named after its host.
+    ExternalService,
+    Database,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Call {
+        ///Index into `actors`.
+        caller: usize,
+        ///Index into `actors`.
+        callee: usize,
+        message: String,
+        return_message: Option<String>,
+        children: Vec<Action>,
+    },
+    ///`body` was repeated `count` times in a row.
+    Loop { count: usize, body: Box<Action> },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ActorGrouping {
+    ///One actor per class that defines a called function.
+    #[default]
+    Class,
+    ///One actor per innermost package in the class map, falling back to the top level class.
+    Package,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SequenceDiagramOptions {
+    pub grouping: ActorGrouping,
+}
+
+/// Builds a sequence diagram from the call tree of `data`. Runs of identical calls are
+/// collapsed into loops.
+pub fn to_sequence_diagram(
+    data: &AppMapObject,
+    options: &SequenceDiagramOptions,
+) -> SequenceDiagram {
+    let tree = CallTree::new(data);
+    let mut packages = HashMap::new();
+    for node in data.class_map.iter() {
+        collect_packages(node, "", None, &mut packages);
+    }
+    let mut builder = Builder {
+        diagram: SequenceDiagram::default(),
+        actors: HashMap::new(),
+        packages,
+        options,
+    };
+    let actions: Vec<Action> = tree
+        .roots
+        .iter()
+        .map(|root| {
+            let caller = builder.actor("caller".to_string(), ActorKind::Caller);
+            builder.action(&tree, *root, caller)
+        })
+        .collect();
+    builder.diagram.actions = collapse_loops(actions);
+    builder.diagram
+}
+
+struct Builder<'a> {
+    diagram: SequenceDiagram,
+    actors: HashMap<String, usize>,
+    ///Class path -> path of the innermost package containing it.
+    packages: HashMap<String, String>,
+    options: &'a SequenceDiagramOptions,
+}
+
+impl Builder<'_> {
+    fn actor(&mut self, name: String, kind: ActorKind) -> usize {
+        if let Some(index) = self.actors.get(&name) {
+            return *index;
+        }
+        self.diagram.actors.push(Actor {
+            name: name.clone(),
+            kind,
+        });
+        self.actors.insert(name, self.diagram.actors.len() - 1);
+        self.diagram.actors.len() - 1
+    }
+
+    fn callee(&mut self, call: &CallObject) -> usize {
+        match &call.type_ {
+            CallObjectType::HttpServerRequest(_) | CallObjectType::HttpServerResponse(_) => {
+                self.actor("HTTP server requests".to_string(), ActorKind::HttpServer)
+            }
+            CallObjectType::HttpClientRequest(x) => {
+                let host = host_of(&x.http_client_request.url).to_string();
+                self.actor(host, ActorKind::ExternalService)
+            }
+            CallObjectType::HttpClientResponse(_) => {
+                self.actor("external service".to_string(), ActorKind::ExternalService)
+            }
+            CallObjectType::SqlQuery(_) => self.actor("Database".to_string(), ActorKind::Database),
+            CallObjectType::Normal | CallObjectType::Function | CallObjectType::Message(_) => {
+                let class = &call.defined_class;
+                match self.options.grouping {
+                    ActorGrouping::Class => self.actor(class.clone(), ActorKind::Class),
+                    ActorGrouping::Package => {
+                        let package = self.packages.get(class).cloned().unwrap_or_else(|| {
+                            class.split("::").next().unwrap_or(class).to_string()
+                        });
+                        self.actor(package, ActorKind::Package)
+                    }
+                }
+            }
+        }
+    }
+
+    fn action(&mut self, tree: &CallTree, index: usize, caller: usize) -> Action {
+        let node = &tree.nodes[index];
+        let callee = self.callee(node.call_object);
+        let message = node
+            .call_object
+            .type_
+            .description()
+            .unwrap_or_else(|| node.call_object.method_id.clone());
+        let return_message = node.return_object.and_then(|ret| match &ret.data {
+            ReturnObjectType::Function(function) => {
+                function.return_value.as_ref().map(|x| x.value.clone())
+            }
+            _ => None,
+        });
+        let children = node
+            .children
+            .iter()
+            .map(|child| self.action(tree, *child, callee))
+            .collect();
+        Action::Call {
+            caller,
+            callee,
+            message,
+            return_message,
+            children: collapse_loops(children),
+        }
+    }
+}
+
+fn collect_packages(
+    node: &CodeObjectType,
+    parent_path: &str,
+    package: Option<&str>,
+    result: &mut HashMap<String, String>,
+) {
+    let (name, children, is_package) = match node {
+        CodeObjectType::Package(p) => (&p.name, p.children.as_ref(), true),
+        CodeObjectType::Class(c) => (&c.name, c.children.as_ref(), false),
+        CodeObjectType::Function(_) => return,
+    };
+    let path = if parent_path.is_empty() {
+        name.clone()
+    } else {
+        format!("{}::{}", parent_path, name)
+    };
+    let package = if is_package {
+        Some(path.as_str())
+    } else {
+        package
+    };
+    if let Some(package) = package {
+        result.insert(path.clone(), package.to_string());
+    }
+    for child in children.into_iter().flatten() {
+        collect_packages(child, &path, package, result);
+    }
+}
+
+fn host_of(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map(|x| x.1).unwrap_or(url);
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+/// Compares two actions ignoring return values, which usually differ between loop iterations.
+fn same_shape(a: &Action, b: &Action) -> bool {
+    match (a, b) {
+        (
+            Action::Call {
+                caller: a_caller,
+                callee: a_callee,
+                message: a_message,
+                children: a_children,
+                ..
+            },
+            Action::Call {
+                caller: b_caller,
+                callee: b_callee,
+                message: b_message,
+                children: b_children,
+                ..
+            },
+        ) => {
+            a_caller == b_caller
+                && a_callee == b_callee
+                && a_message == b_message
+                && a_children.len() == b_children.len()
+                && a_children
+                    .iter()
+                    .zip(b_children.iter())
+                    .all(|(a, b)| same_shape(a, b))
+        }
+        (
+            Action::Loop {
+                count: a_count,
+                body: a_body,
+            },
+            Action::Loop {
+                count: b_count,
+                body: b_body,
+            },
+        ) => a_count == b_count && same_shape(a_body, b_body),
+        _ => false,
+    }
+}
+
+fn collapse_loops(actions: Vec<Action>) -> Vec<Action> {
+    let mut result: Vec<Action> = vec![];
+    let mut run = 0;
+    for action in actions {
+        let repeated = match result.last() {
+            Some(Action::Loop { body, .. }) if run > 1 => same_shape(body, &action),
+            Some(last) => same_shape(last, &action),
+            None => false,
+        };
+        if !repeated {
+            run = 1;
+            result.push(action);
+            continue;
+        }
+        run += 1;
+        let last = result.pop().expect("repeated actions have a predecessor");
+        result.push(match last {
+            Action::Loop { body, .. } if run > 2 => Action::Loop { count: run, body },
+            last => Action::Loop {
+                count: run,
+                body: Box::new(last),
+            },
+        });
+    }
+    result
+}
+
+impl SequenceDiagram {
+    pub fn to_plantuml(&self) -> String {
+        let mut out = String::from("@startuml\n");
+        for (index, actor) in self.actors.iter().enumerate() {
+            let keyword = match actor.kind {
+                ActorKind::Caller => "actor",
+                ActorKind::Database => "database",
+                ActorKind::ExternalService | ActorKind::HttpServer => "boundary",
+                ActorKind::Package => "collections",
+                ActorKind::Class => "participant",
+            };
+            out.push_str(&format!(
+                "{} \"{}\" as a{}\n",
+                keyword,
+                actor.name.replace('"', "'"),
+                index
+            ));
+        }
+        for action in self.actions.iter() {
+            write_plantuml_action(&mut out, action);
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("sequenceDiagram\n");
+        for (index, actor) in self.actors.iter().enumerate() {
+            let keyword = match actor.kind {
+                ActorKind::Caller => "actor",
+                _ => "participant",
+            };
+            out.push_str(&format!(
+                "    {} a{} as {}\n",
+                keyword,
+                index,
+                mermaid_text(&actor.name)
+            ));
+        }
+        for action in self.actions.iter() {
+            write_mermaid_action(&mut out, action, 1);
+        }
+        out
+    }
+}
+
+/// Messages are single line and trimmed to 100 characters like AppMap parameter values.
+fn message_text(message: &str) -> String {
+    let text = message.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(100) {
+        Some((index, _)) => format!("{}...", &text[..index]),
+        None => text,
+    }
+}
+
+fn mermaid_text(message: &str) -> String {
+    message_text(message)
+        .chars()
+        .map(|c| match c {
+            ';' => "#59;".to_string(),
+            '#' => "#35;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+fn write_plantuml_action(out: &mut String, action: &Action) {
+    match action {
+        Action::Call {
+            caller,
+            callee,
+            message,
+            return_message,
+            children,
+        } => {
+            out.push_str(&format!(
+                "a{} -> a{} : {}\n",
+                caller,
+                callee,
+                message_text(message)
+            ));
+            for child in children.iter() {
+                write_plantuml_action(out, child);
+            }
+            if caller != callee {
+                let text = return_message.as_deref().unwrap_or("return");
+                out.push_str(&format!(
+                    "a{} --> a{} : {}\n",
+                    callee,
+                    caller,
+                    message_text(text)
+                ));
+            }
+        }
+        Action::Loop { count, body } => {
+            out.push_str(&format!("loop {} times\n", count));
+            write_plantuml_action(out, body);
+            out.push_str("end\n");
+        }
+    }
+}
+
+fn write_mermaid_action(out: &mut String, action: &Action, depth: usize) {
+    let indent = "    ".repeat(depth);
+    match action {
+        Action::Call {
+            caller,
+            callee,
+            message,
+            return_message,
+            children,
+        } => {
+            out.push_str(&format!(
+                "{}a{}->>a{}: {}\n",
+                indent,
+                caller,
+                callee,
+                mermaid_text(message)
+            ));
+            for child in children.iter() {
+                write_mermaid_action(out, child, depth);
+            }
+            if caller != callee {
+                let text = return_message.as_deref().unwrap_or("return");
+                out.push_str(&format!(
+                    "{}a{}-->>a{}: {}\n",
+                    indent,
+                    callee,
+                    caller,
+                    mermaid_text(text)
+                ));
+            }
+        }
+        Action::Loop { count, body } => {
+            out.push_str(&format!("{}loop {} times\n", indent, count));
+            write_mermaid_action(out, body, depth + 1);
+            out.push_str(&format!("{}end\n", indent));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::appmap_definition::*;
+use crate::labels::LABELS_FIELD;
+use crate::parameters::json_parameter;
+use crate::AppMap;
+
+/// One line written by `tracing_subscriber::fmt().json()`.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct LogLine {
+    #[serde(default)]
+    timestamp: Option<String>,
+    #[serde(default)]
+    fields: Map<String, Value>,
+    #[serde(default)]
+    target: Option<String>,
+    #[serde(default)]
+    filename: Option<PathBuf>,
+    #[serde(default)]
+    line_number: Option<usize>,
+    ///Name and fields of the span of the event. For span events, the span itself.
+    #[serde(default)]
+    span: Option<Map<String, Value>>,
+    #[serde(rename = "threadId")]
+    #[serde(default)]
+    thread_id: Option<String>,
+    #[serde(rename = "threadName")]
+    #[serde(default)]
+    thread_name: Option<String>,
+    ///The fields of the event if the log was written with `flatten_event(true)`.
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl LogLine {
+    fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name).or_else(|| self.other.get(name))
+    }
+}
+
+/// A span of the log, from its first `new` or `enter` event to its `close` event.
+#[derive(Debug)]
+struct OpenSpan {
+    ///Name and fields of the span in its last event.
+    fields: Map<String, Value>,
+    call: Option<OpenCall>,
+    entered: bool,
+}
+
+/// The index of the last span matching `predicate`, preferring spans with the same `fields`
+/// since fields can be recorded after the span was created.
+fn find_span(
+    spans: &[OpenSpan],
+    fields: &Map<String, Value>,
+    predicate: impl Fn(&OpenSpan) -> bool,
+) -> Option<usize> {
+    spans
+        .iter()
+        .rposition(|x| predicate(x) && x.fields == *fields)
+        .or_else(|| spans.iter().rposition(predicate))
+}
+
+#[derive(Debug)]
+struct OpenCall {
+    event_id: EventId,
+    thread_id: u32,
+    timestamp: Option<f64>,
+    return_value: Option<ParameterObject>,
+}
+
+/// Reads a log written by `tracing_subscriber::fmt().json()` with span events, see
+/// [from_tracing_json].
+pub fn read_tracing_json(path: impl AsRef<Path>) -> Result<AppMapObject, Box<dyn Error>> {
+    from_tracing_json(BufReader::new(File::open(path)?))
+}
+
+/// Reconstructs an AppMap from a log written by `tracing_subscriber::fmt().json()` with
+/// `with_span_events(FmtSpan::FULL)`, like [crate::AppMapLayer] would have recorded it.
+///
+/// Every span becomes a call on its first `enter` event and returns on its `close` event, or
+/// on its `exit` event if the log has no `close` events. The fields of a span are its
+/// parameters and its `return` event, as written by `#[instrument(ret)]`, is its return
+/// value. Threads are taken from `with_thread_ids(true)` or `with_thread_names(true)`, locations
+/// from `with_file(true)` and `with_line_number(true)`. Other events and lines which are not
+/// JSON objects are ignored.
+///
+/// The log does not identify spans, so spans with the same target and name are told apart by
+/// their fields and the order of their events only. Concurrent spans with equal fields may be
+/// mixed up.
+pub fn from_tracing_json(reader: impl BufRead) -> Result<AppMapObject, Box<dyn Error>> {
+    let mut lines = vec![];
+    for line in reader.lines() {
+        if let Ok(line) = serde_json::from_str::<LogLine>(&line?) {
+            lines.push(line);
+        }
+    }
+    let returns_on_close = lines
+        .iter()
+        .any(|x| message(x) == Some("close") && x.span.is_some());
+
+    let mut app_map = AppMap::new();
+    let mut threads = ThreadIds::default();
+    let mut open: HashMap<String, Vec<OpenSpan>> = HashMap::new();
+    for line in lines.iter() {
+        let Some(span) = line.span.as_ref() else {
+            continue;
+        };
+        let key = format!(
+            "{}::{}",
+            line.target.as_deref().unwrap_or_default(),
+            span.get("name")
+                .and_then(|x| x.as_str())
+                .unwrap_or_default()
+        );
+        let spans = open.entry(key).or_default();
+        let timestamp = line.timestamp.as_deref().and_then(parse_timestamp);
+        let new_span = || OpenSpan {
+            fields: span.clone(),
+            call: None,
+            entered: false,
+        };
+        match message(line) {
+            Some("new") => spans.push(new_span()),
+            Some("enter") => {
+                let index = match find_span(spans, span, |x| !x.entered) {
+                    Some(index) => index,
+                    None => {
+                        spans.push(new_span());
+                        spans.len() - 1
+                    }
+                };
+                let open_span = &mut spans[index];
+                open_span.fields = span.clone();
+                open_span.entered = true;
+                if open_span.call.is_none() {
+                    let thread_id = threads.get(line);
+                    open_span.call = Some(OpenCall {
+                        event_id: add_call(&mut app_map, line, span, thread_id, timestamp),
+                        thread_id,
+                        timestamp,
+                        return_value: None,
+                    });
+                }
+            }
+            Some("exit") => {
+                let Some(index) = find_span(spans, span, |x| x.entered) else {
+                    continue;
+                };
+                spans[index].fields = span.clone();
+                spans[index].entered = false;
+                if !returns_on_close {
+                    let open_span = spans.remove(index);
+                    add_return(&mut app_map, open_span, timestamp);
+                }
+            }
+            Some("close") => {
+                let index = find_span(spans, span, |x| x.call.is_some())
+                    .or_else(|| find_span(spans, span, |_| true));
+                if let Some(index) = index {
+                    let open_span = spans.remove(index);
+                    add_return(&mut app_map, open_span, timestamp);
+                }
+            }
+            _ => {
+                let Some(value) = line.field("return") else {
+                    continue;
+                };
+                let index = find_span(spans, span, |x| x.entered && x.call.is_some());
+                if let Some(call) = index.and_then(|x| spans[x].call.as_mut()) {
+                    call.return_value = Some(json_parameter(None, value));
+                }
+            }
+        }
+    }
+
+    let mut data = app_map.data;
+    data.metadata = Some(MetadataObject {
+        language: Some(LanguageObject {
+            name: "rust".to_string(),
+            ..Default::default()
+        }),
+        recorder: Some(RecorderObject {
+            name: "tracing-json".to_string(),
+            type_: Some("process".to_string()),
+        }),
+        ..Default::default()
+    });
+    Ok(data)
+}
+
+fn message(line: &LogLine) -> Option<&str> {
+    line.field("message").and_then(|x| x.as_str())
+}
+
+fn add_call(
+    app_map: &mut AppMap,
+    line: &LogLine,
+    span: &Map<String, Value>,
+    thread_id: u32,
+    timestamp: Option<f64>,
+) -> EventId {
+    let class = line.target.clone().unwrap_or_default();
+    let method = span
+        .get("name")
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let mut labels = vec![];
+    let mut parameters = vec![];
+    for (name, value) in span.iter() {
+        if name == LABELS_FIELD {
+            labels.extend(
+                value
+                    .as_str()
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty()),
+            );
+        } else if name != "name" && !name.starts_with("appmap.") {
+            parameters.push(json_parameter(Some(name), value));
+        }
+    }
+    let event_id = app_map.add_function_call_event(
+        thread_id,
+        class.clone(),
+        method.clone(),
+        line.filename.clone(),
+        line.line_number,
+        true,
+        Some(parameters).filter(|x| !x.is_empty()),
+    );
+    app_map.set_timestamp(event_id, timestamp);
+    app_map.add_function_labels(&class, &method, &labels);
+    event_id
+}
+
+fn add_return(app_map: &mut AppMap, open_span: OpenSpan, timestamp: Option<f64>) {
+    let Some(call) = open_span.call else {
+        return;
+    };
+    let elapsed = timestamp
+        .zip(call.timestamp)
+        .map(|(end, start)| (end - start).max(0.0));
+    let data = match call.return_value {
+        Some(return_value) => ReturnObjectType::Function(FunctionReturnObject {
+            return_value: Some(return_value),
+            exceptions: None,
+        }),
+        None => ReturnObjectType::Normal,
+    };
+    let return_id = app_map.add_function_return_event(call.thread_id, call.event_id, elapsed, data);
+    app_map.set_timestamp(return_id, timestamp);
+}
+
+/// Numbers the threads of the log in the order they appear, starting at 1 like the threads
+/// recorded by [crate::AppMapLayer]. Every event is on thread 1 if the log has no threads.
+#[derive(Debug, Default)]
+struct ThreadIds {
+    ids: HashMap<String, u32>,
+}
+
+impl ThreadIds {
+    fn get(&mut self, line: &LogLine) -> u32 {
+        let Some(thread) = line.thread_id.as_ref().or(line.thread_name.as_ref()) else {
+            return 1;
+        };
+        let next_id = self.ids.len() as u32 + 1;
+        *self.ids.entry(thread.clone()).or_insert(next_id)
+    }
+}
+
+/// Seconds since the Unix epoch of an RFC 3339 timestamp like "2024-05-01T12:00:00.123456Z".
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (date, time) = timestamp.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: i64 = date.next()?.parse().ok()?;
+    let day: i64 = date.next()?.parse().ok()?;
+
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(index) => time.split_at(index),
+        None => (time, ""),
+    };
+    let mut time = time.splitn(3, ':');
+    let hour: f64 = time.next()?.parse().ok()?;
+    let minute: f64 = time.next()?.parse().ok()?;
+    let second: f64 = time.next()?.parse().ok()?;
+    let offset = match offset.split_at_checked(1) {
+        Some((sign @ ("+" | "-"), offset)) => {
+            let (hours, minutes) = offset.split_once(':').unwrap_or((offset, "0"));
+            let offset = hours.parse::<f64>().ok()? * 3600.0 + minutes.parse::<f64>().ok()? * 60.0;
+            if sign == "-" {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => 0.0,
+    };
+
+    // days since 1970-01-01 of the proleptic Gregorian calendar
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(days as f64 * 86_400.0 + hour * 3600.0 + minute * 60.0 + second - offset)
+}
@@ -0,0 +1,85 @@
+use std::error::Error;
+
+use tracing::level_filters::LevelFilter;
+use tracing::Metadata;
+
+use crate::config::{AppMapConfig, PackageConfig};
+
+/// Decides which spans [crate::AppMapLayer] records, following the `packages`, `exclude` and
+/// `level` settings of `appmap.yml`.
+///
+/// A pattern matches a span if it is its target or a module above it (`tokio` matches the
+/// target `tokio::runtime`), or if it is the end of the span's `target::name` path (`Type::method`
+/// and `method` both match a span named `method` with the target `my_crate::Type`, and a span
+/// named `Type::method`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanFilter {
+    packages: Vec<PackageConfig>,
+    exclude: Vec<String>,
+    level: Option<LevelFilter>,
+}
+
+impl SpanFilter {
+    pub fn from_config(config: &AppMapConfig) -> Result<Self, Box<dyn Error>> {
+        let level = config
+            .level
+            .as_deref()
+            .map(|x| {
+                x.parse::<LevelFilter>()
+                    .map_err(|e| format!("invalid level {:?}: {}", x, e))
+            })
+            .transpose()?;
+        Ok(Self {
+            packages: config.packages.clone(),
+            exclude: config.exclude.clone(),
+            level,
+        })
+    }
+
+    /// Whether spans with this metadata are recorded. Does not allocate.
+    pub fn is_recorded(&self, metadata: &Metadata<'_>) -> bool {
+        if let Some(level) = self.level {
+            if *metadata.level() > level {
+                return false;
+            }
+        }
+        let target = metadata.target();
+        let name = metadata.name();
+        if self.exclude.iter().any(|x| matches(x, target, name)) {
+            return false;
+        }
+        self.packages.is_empty()
+            || self.packages.iter().any(|package| {
+                is_module_or_below(&package.path, target)
+                    && !package.exclude.iter().any(|x| matches(x, target, name))
+            })
+    }
+}
+
+pub(crate) fn matches(pattern: &str, target: &str, name: &str) -> bool {
+    is_module_or_below(pattern, target) || is_end_of_path(pattern, target, name)
+}
+
+fn is_module_or_below(module: &str, target: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Whether `pattern` is the end of `target::name`, split at `::`.
+fn is_end_of_path(pattern: &str, target: &str, name: &str) -> bool {
+    let Some(qualifier) = pattern.strip_suffix(name) else {
+        return false;
+    };
+    if qualifier.is_empty() {
+        return true;
+    }
+    let Some(qualifier) = qualifier.strip_suffix("::") else {
+        return false;
+    };
+    match target.strip_suffix(qualifier) {
+        Some(rest) => rest.is_empty() || rest.ends_with("::"),
+        None => false,
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt::Debug;
+
+use tracing::field::{Field, Visit};
+use tracing::Metadata;
+
+use crate::config::{AppMapConfig, FunctionConfig};
+use crate::filter::matches;
+
+/// Name of the span field whose comma separated value is added to the labels of the function:
+///
+/// ```ignore
+/// #[instrument(fields(appmap.labels = "security,deserialize"))]
+/// fn parse_token(token: &str) {}
+/// ```
+pub const LABELS_FIELD: &str = "appmap.labels";
+
+/// Labels functions following the `functions` setting of `appmap.yml`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LabelRules {
+    functions: Vec<FunctionConfig>,
+}
+
+impl LabelRules {
+    pub fn from_config(config: &AppMapConfig) -> Self {
+        Self {
+            functions: config.functions.clone(),
+        }
+    }
+
+    /// The labels of every rule matching spans with this metadata.
+    pub fn labels_for<'a>(&'a self, metadata: &'a Metadata<'_>) -> impl Iterator<Item = &'a str> {
+        self.functions
+            .iter()
+            .filter(|x| matches(&x.name, metadata.target(), metadata.name()))
+            .flat_map(|x| x.labels.iter().map(String::as_str))
+    }
+}
+
+/// Collects the labels given by the [LABELS_FIELD] of a span.
+#[derive(Debug, Default)]
+pub(crate) struct LabelsVisitor {
+    pub labels: Vec<String>,
+}
+
+impl Visit for LabelsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == LABELS_FIELD {
+            self.labels.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(String::from),
+            );
+        }
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == LABELS_FIELD {
+            self.record_str(field, &format!("{:?}", value));
+        }
+    }
+}
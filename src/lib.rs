@@ -1,43 +1,346 @@
 use std::error::Error;
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
-use tracing::field::Field;
+use tracing::field::Visit;
 use tracing::span::{Attributes, Record};
-use tracing::{Event, Id, Instrument, Subscriber};
-use tracing_subscriber::field::Visit;
+use tracing::{Event, Id, Metadata, Span, Subscriber};
 use tracing_subscriber::layer::Context;
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
 use tracing_subscriber::Layer;
 
 use crate::appmap_definition::*;
+use crate::config::AppMapConfig;
 use crate::extensions::OptionVecExtensions;
+use crate::filter::SpanFilter;
+use crate::labels::{LabelRules, LabelsVisitor};
+use crate::limits::{EventLimits, LimitState, RotatedPart};
 use crate::node_functions::*;
+use crate::parameters::ParametersVisitor;
+use crate::recording::{RecordingGuard, RecordingScope, Recordings};
+use crate::redaction::{Redactor, SECRET_LABEL};
+use crate::sampling::{Sampler, Unsampled};
+use crate::streaming::AppMapStreamWriter;
 
 pub mod appmap_definition;
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub mod config;
+pub mod convert;
+pub mod filter;
+pub mod labels;
+pub mod limits;
+pub mod recording;
+pub mod redaction;
+#[cfg(feature = "remote-recording")]
+pub mod remote_recording;
+pub mod sampling;
+pub mod streaming;
+pub mod termination;
+#[doc(hidden)]
+pub mod test_support;
+
+pub use appmap_tracing_test_macros::{appmap_test, appmap_tokio_test};
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AppMap {
     #[serde(flatten)]
     pub data: AppMapObject,
     #[serde(skip)]
     next_event_id: u64,
+    #[serde(skip)]
+    limit_state: LimitState,
 }
 
-#[derive(Debug)]
+/// Records every span as a function call.
+///
+/// The layer is cheap to clone; all clones record into the same map, so a clone can be kept
+/// to [AppMapLayer::finish] the recording after the layer was handed to a subscriber.
+#[derive(Debug, Clone)]
 pub struct AppMapLayer {
-    pub test: Mutex<AppMap>,
+    pub test: Arc<Mutex<AppMap>>,
+    writer: Arc<Mutex<Option<AppMapStreamWriter<BufWriter<File>>>>>,
+    ///File [AppMapLayer::finish] writes the map to if the layer does not stream.
+    output: PathBuf,
+    ///The first error of writing an event to `writer`, returned by [AppMapLayer::finish].
+    write_error: Arc<Mutex<Option<String>>>,
+    recordings: Arc<Mutex<Recordings>>,
+    filter: Arc<SpanFilter>,
+    label_rules: Arc<LabelRules>,
+    redactor: Arc<Redactor>,
+    sampler: Arc<Sampler>,
+}
+
+/// Directory the map is written to without a configuration.
+const DEFAULT_APPMAP_DIR: &str = "maps/tmp";
+/// Name of the file written to the `appmap_dir` by [AppMapLayer::finish].
+const MAP_FILE_NAME: &str = "test_1.appmap.json";
+
+/// Name of the field holding the return value in the event `#[instrument(ret)]` emits before
+/// the span is exited.
+const RETURN_FIELD: &str = "return";
+
+/// Stored in the extensions of every span that passed the [SpanFilter].
+#[derive(Debug)]
+struct Included {
+    ///Labels given by the [labels::LABELS_FIELD] of the span.
+    labels: Vec<String>,
+    ///The redacted fields of the span.
+    parameters: Vec<ParameterObject>,
+    ///Spans the span follows from.
+    follows_from: Vec<Id>,
+}
+
+/// Stored in the extensions of every span that was recorded as a call.
+#[derive(Debug)]
+struct RecordedCall {
+    event_id: EventId,
+    ///Id of each scoped recording the call was recorded in, with the id of the call event in it.
+    scoped_event_ids: Vec<(u64, EventId)>,
+    thread_id: u32,
+    ///Thread the span is entered on right now, if it is entered.
+    entered_on: Option<u32>,
+    start: Instant,
+    ///Recorded by the `return` event of `#[instrument(ret)]`.
+    return_value: Option<ParameterObject>,
+}
+
+impl RecordedCall {
+    /// The id of the call event in every map it was recorded in, with the id of the scoped
+    /// recording or 0 for the default map.
+    fn event_ids(&self) -> impl Iterator<Item = (u64, EventId)> + '_ {
+        std::iter::once((0, self.event_id)).chain(self.scoped_event_ids.iter().copied())
+    }
 }
 
 impl AppMapLayer {
     pub fn new() -> Self {
         Self {
-            test: Mutex::new(AppMap::new()),
+            test: Arc::new(Mutex::new(AppMap::new())),
+            writer: Arc::new(Mutex::new(None)),
+            output: Path::new(DEFAULT_APPMAP_DIR).join(MAP_FILE_NAME),
+            write_error: Arc::new(Mutex::new(None)),
+            recordings: Arc::new(Mutex::new(Recordings::default())),
+            filter: Arc::new(SpanFilter::default()),
+            label_rules: Arc::new(LabelRules::default()),
+            redactor: Arc::new(Redactor::default()),
+            sampler: Arc::new(Sampler::default()),
+        }
+    }
+    /// Creates a layer that streams every event to `path` as soon as it is recorded instead of
+    /// keeping it in memory. Only the class map and metadata stay in memory until
+    /// [AppMapLayer::finish] writes them.
+    pub fn streaming(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = File::create(path.as_ref())?;
+        let writer = AppMapStreamWriter::new(BufWriter::new(file), "1.12")?;
+        Ok(Self {
+            test: Arc::new(Mutex::new(AppMap::new())),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            output: path.as_ref().to_path_buf(),
+            write_error: Arc::new(Mutex::new(None)),
+            recordings: Arc::new(Mutex::new(Recordings::default())),
+            filter: Arc::new(SpanFilter::default()),
+            label_rules: Arc::new(LabelRules::default()),
+            redactor: Arc::new(Redactor::default()),
+            sampler: Arc::new(Sampler::default()),
+        })
+    }
+    /// Only records the spans accepted by `filter`. Spans created before the filter was set
+    /// are not affected.
+    pub fn with_filter(mut self, filter: SpanFilter) -> Self {
+        self.filter = Arc::new(filter);
+        self
+    }
+    /// Labels the recorded functions following `rules`.
+    pub fn with_label_rules(mut self, rules: LabelRules) -> Self {
+        self.label_rules = Arc::new(rules);
+        self
+    }
+    /// Redacts the recorded values with `redactor` instead of [Redactor::default].
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+    /// Writes the map to `path` instead of `maps/tmp/test_1.appmap.json`. Has no effect on a
+    /// streaming layer.
+    pub fn with_output(mut self, path: impl Into<PathBuf>) -> Self {
+        if self.writer.lock().unwrap().is_none() {
+            self.output = path.into();
+        }
+        self
+    }
+    /// Applies the filter, label rules, redaction and sampling of `config` and writes the map
+    /// to its `appmap_dir`.
+    pub fn with_config(self, config: &AppMapConfig) -> Result<Self, Box<dyn Error>> {
+        Ok(self
+            .with_output(config.appmap_dir().join(MAP_FILE_NAME))
+            .with_filter(SpanFilter::from_config(config)?)
+            .with_label_rules(LabelRules::from_config(config))
+            .with_redactor(Redactor::from_config(&config.redaction)?)
+            .with_sampler(Sampler::from_config(&config.sampling)?))
+    }
+    /// Only records the calls selected by `sampler`.
+    pub fn with_sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = Arc::new(sampler);
+        self
+    }
+    /// Bounds the number or size of the events kept in memory by the default recording and by
+    /// every recording started afterwards. Has no effect on the default recording of a
+    /// streaming layer, which does not keep events in memory.
+    pub fn with_limits(self, limits: EventLimits) -> Self {
+        self.test.lock().unwrap().set_limits(limits.clone());
+        self.recordings.lock().unwrap().limits = Some(limits);
+        self
+    }
+    /// Writes the recording: a streaming layer completes its file, any other layer writes the
+    /// whole map to its output with [AppMap::write_to_file]. Fails if an event could not be
+    /// streamed.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        let mut app_map = self.test.lock().unwrap();
+        match self.writer.lock().unwrap().take() {
+            Some(mut writer) => {
+                for event in app_map.take_events() {
+                    writer.write_event(&event)?;
+                }
+                writer.finish(&app_map.data.class_map, app_map.data.metadata.as_ref())?;
+                match self.write_error.lock().unwrap().take() {
+                    Some(e) => Err(format!("could not write AppMap events: {}", e).into()),
+                    None => Ok(()),
+                }
+            }
+            None => app_map.write_to_file(&self.output),
+        }
+    }
+    /// Starts an additional recording of every span entered from now on until the returned
+    /// guard is stopped. It is independent of the process wide recording and of other
+    /// recordings.
+    pub fn start_recording(&self, name: impl Into<String>) -> RecordingGuard {
+        let id = self
+            .recordings
+            .lock()
+            .unwrap()
+            .start(name.into(), RecordingScope::All);
+        RecordingGuard::new(self.clone(), id)
+    }
+    /// Starts a recording of `span` and all spans below it, e.g. the span of one HTTP request
+    /// or test. The recording has to be started before `span` is entered for the first time
+    /// to contain the call of `span` itself.
+    pub fn start_recording_in_span(&self, name: impl Into<String>, span: &Span) -> RecordingGuard {
+        let scope = match span.id() {
+            Some(id) => RecordingScope::Span(id),
+            None => RecordingScope::Nothing,
+        };
+        let id = self.recordings.lock().unwrap().start(name.into(), scope);
+        RecordingGuard::new(self.clone(), id)
+    }
+    pub(crate) fn stop_recording(&self, id: u64) -> Option<AppMapObject> {
+        self.recordings.lock().unwrap().stop(id)
+    }
+    /// Adds the labels and redacted parameters given by the fields of a span.
+    fn record_fields(&self, included: &mut Included, record: impl Fn(&mut dyn Visit)) {
+        let mut labels = LabelsVisitor::default();
+        record(&mut labels);
+        included.labels.append(&mut labels.labels);
+        let mut parameters = ParametersVisitor::default();
+        record(&mut parameters);
+        if self.redactor.redact_parameters(&mut parameters.parameters)
+            && !included.labels.iter().any(|x| x == SECRET_LABEL)
+        {
+            included.labels.push(SECRET_LABEL.to_string());
+        }
+        for parameter in parameters.parameters {
+            match included
+                .parameters
+                .iter_mut()
+                .find(|x| x.name == parameter.name)
+            {
+                Some(existing) => *existing = parameter,
+                None => included.parameters.push(parameter),
+            }
+        }
+    }
+    /// Adds the return event of `call` to the map and to the scoped recordings it is part of.
+    fn record_return(&self, call: &RecordedCall, data: ReturnObjectType) {
+        let elapsed = Some(call.start.elapsed().as_secs_f64());
+        let mut app_map = self.test.lock().unwrap();
+        app_map.add_function_return_event(call.thread_id, call.event_id, elapsed, data.clone());
+        self.flush_events(&mut app_map);
+        let mut rotated_parts = app_map.take_rotated_parts();
+        drop(app_map);
+        let mut recordings = self.recordings.lock().unwrap();
+        add_scoped_returns(&mut recordings, call, elapsed, &data);
+        rotated_parts.append(&mut recordings.take_rotated_parts());
+        drop(recordings);
+        rotated_parts.iter().for_each(RotatedPart::write);
+    }
+    /// Counts the call of `span`, which is not recorded because of the [Sampler], in the map
+    /// and in the scoped recordings it is part of.
+    fn add_unrecorded_call<S: for<'lookup> LookupSpan<'lookup>>(&self, span: &SpanRef<'_, S>) {
+        let metadata = span.metadata();
+        let path = metadata.file().map(Path::new);
+        let lineno = metadata.line().map(|x| x as usize);
+        let count = |app_map: &mut AppMap| {
+            app_map.add_unrecorded_call(metadata.target(), metadata.name(), path, lineno)
+        };
+        count(&mut self.test.lock().unwrap());
+        let span_scope: Vec<Id> = span.scope().map(|x| x.id()).collect();
+        for recording in self.recordings.lock().unwrap().matching(&span_scope) {
+            count(&mut recording.app_map);
+        }
+    }
+    /// Adds `labels` to the function of `call` in the map and in the scoped recordings it is
+    /// part of.
+    fn add_labels(&self, call: &RecordedCall, metadata: &Metadata<'_>, labels: &[String]) {
+        let (class, method) = (metadata.target(), metadata.name());
+        self.test
+            .lock()
+            .unwrap()
+            .add_function_labels(class, method, labels);
+        let mut recordings = self.recordings.lock().unwrap();
+        for (recording_id, _) in call.scoped_event_ids.iter() {
+            if let Some(recording) = recordings.get_mut(*recording_id) {
+                recording.app_map.add_function_labels(class, method, labels);
+            }
         }
     }
+    /// The redacted value of the [RETURN_FIELD] of `event`.
+    fn return_value(&self, event: &Event<'_>) -> Option<ParameterObject> {
+        let mut visitor = ParametersVisitor::default();
+        event.record(&mut visitor);
+        let mut return_value = visitor
+            .parameters
+            .into_iter()
+            .find(|x| x.name.as_deref() == Some(RETURN_FIELD))?;
+        return_value.name = None;
+        self.redactor.redact_parameter(&mut return_value);
+        Some(return_value)
+    }
+    /// Moves the recorded events to the stream writer, if there is one.
+    fn flush_events(&self, app_map: &mut AppMap) {
+        if let Some(writer) = self.writer.lock().unwrap().as_mut() {
+            for event in app_map.take_events() {
+                if let Err(e) = writer.write_event(&event) {
+                    self.write_error
+                        .lock()
+                        .unwrap()
+                        .get_or_insert_with(|| e.to_string());
+                }
+            }
+        }
+    }
+}
+impl Default for AppMapLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Default for AppMap {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl AppMap {
     pub fn new() -> Self {
@@ -50,6 +353,7 @@ impl AppMap {
                 event_updates: None,
             },
             next_event_id: 1,
+            limit_state: LimitState::default(),
         }
     }
     pub fn get_next_event_id(&mut self) -> u64 {
@@ -58,6 +362,7 @@ impl AppMap {
         x
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_function_call_event(
         &mut self,
         thread_id: u32,
@@ -66,32 +371,106 @@ impl AppMap {
         path: Option<PathBuf>,
         lineno: Option<usize>,
         is_static: bool,
-    ) {
-        let id = self.get_next_event_id();
+        parameters: Option<Vec<ParameterObject>>,
+    ) -> EventId {
+        let id = EventId::from(self.get_next_event_id());
+        self.add_function_to_class_map(&class, &method, path.as_deref(), lineno);
         // let class_name = class.clone();
         // let class_name = class_name.rsplit_once("::").unwrap_or(("", &class)).1;
-        self.data.events.push(EventObject {
-            id: EventId::from(id),
+        self.push_event(EventObject {
+            id,
             thread_id,
+            timestamp: Some(now_timestamp()),
             event: EventObjectType::Call(CallObject {
-                defined_class: class.to_string(),
-                method_id: method.clone(),
-                path: path.clone(),
+                defined_class: class,
+                method_id: method,
+                path,
                 lineno,
                 receiver: None,
-                parameters: None,
+                parameters,
                 is_static,
+                caused_by: None,
                 type_: CallObjectType::Function,
             }),
         });
-        let existing_node = self.find_in_class_map(&class, &method);
+        id
+    }
+    pub fn add_function_return_event(
+        &mut self,
+        thread_id: u32,
+        parent_id: EventId,
+        elapsed: Option<f64>,
+        data: ReturnObjectType,
+    ) -> EventId {
+        let id = EventId::from(self.get_next_event_id());
+        self.push_event(EventObject {
+            id,
+            thread_id,
+            timestamp: Some(now_timestamp()),
+            event: EventObjectType::Return(ReturnObject {
+                parent_id,
+                elapsed,
+                data,
+            }),
+        });
+        id
+    }
+    /// The event with the id `event_id`, unless the limits of the map dropped it.
+    pub(crate) fn event_mut(&mut self, event_id: EventId) -> Option<&mut EventObject> {
+        self.data.events.iter_mut().rev().find(|x| x.id == event_id)
+    }
+    /// Replaces the time of the event with the id `event_id`, e.g. by the time of a converted
+    /// event.
+    pub(crate) fn set_timestamp(&mut self, event_id: EventId, timestamp: Option<f64>) {
+        if let Some(event) = self.event_mut(event_id) {
+            event.timestamp = timestamp;
+        }
+    }
+    /// Records that a task running `name` of `class` was spawned: a call and return of
+    /// `class::spawn` on `thread_id`, which has to be the thread the spawning call is entered
+    /// on right now, with the name of the task as parameter. Returns the id of the call event,
+    /// or `None` if the limits of the map dropped it.
+    pub fn add_spawn_event(&mut self, thread_id: u32, class: &str, name: &str) -> Option<EventId> {
+        let event_id = self.add_function_call_event(
+            thread_id,
+            class.to_string(),
+            "spawn".to_string(),
+            None,
+            None,
+            true,
+            Some(vec![parameters::parameter(
+                "task",
+                "&str",
+                name.to_string(),
+            )]),
+        );
+        let kept = !self.is_dropped(event_id);
+        self.add_function_return_event(thread_id, event_id, Some(0.0), ReturnObjectType::Normal);
+        Some(event_id).filter(|_| kept)
+    }
+    /// Sets the `caused_by` of the call event with the id `event_id`, if there are causes.
+    pub fn set_caused_by(&mut self, event_id: EventId, caused_by: Vec<EventId>) {
+        if caused_by.is_empty() {
+            return;
+        }
+        if let Some(EventObjectType::Call(call)) = self.event_mut(event_id).map(|x| &mut x.event) {
+            call.caused_by = Some(caused_by);
+        }
+    }
+    pub fn add_function_to_class_map(
+        &mut self,
+        class: &str,
+        method: &str,
+        path: Option<&Path>,
+        lineno: Option<usize>,
+    ) {
+        let existing_node = self.find_in_class_map(class, method);
         if existing_node.is_none() {
             // println!("node not found: {} ; {}", class, method);
             self.add_func_to_hierarchy(
-                class,
-                method,
-                path.map(|x| x.to_str().map(|x| format!("{}:{}", x, lineno.unwrap_or(0))))
-                    .flatten(),
+                class.to_string(),
+                method.to_string(),
+                path.and_then(|x| x.to_str().map(|x| format!("{}:{}", x, lineno.unwrap_or(0)))),
             );
         } else {
             // println!(
@@ -108,6 +487,7 @@ impl AppMap {
             labels: None,
             comment: None,
             source: None,
+            unrecorded_calls: None,
         };
         let func = CodeObjectType::Function(func);
 
@@ -152,9 +532,7 @@ impl AppMap {
         // println!("got add request for top level class: {}", class);
 
         let top_level_class = self.find_class_in_class_map_mut(class);
-        if top_level_class.is_some() {
-            return;
-        } else {
+        if top_level_class.is_none() {
             let class_node = CodeObjectType::Class(ClassCodeObject {
                 name: class.to_string(),
                 children: None,
@@ -166,21 +544,17 @@ impl AppMap {
         }
     }
 
-    fn find_class_in_class_map(&self, class: &str) -> Option<&ClassCodeObject> {
-        for node in self.data.class_map.iter() {
-            let class_node = find_class_in_tree(node, class);
-            if class_node.is_some() {
-                return class_node;
-            }
-        }
-        None
-    }
-    fn find_in_class_map(&self, class: &str, method: &str) -> Option<&ClassCodeObject> {
+    fn find_in_class_map(&self, class: &str, method: &str) -> Option<&CodeObjectType> {
         for node in self.data.class_map.iter() {
             let class_node = find_class_in_tree(node, class);
             if let Some(class_node) = class_node {
-                if class_node.name == method {
-                    return Some(class_node);
+                if let Some(children) = class_node.children.as_ref() {
+                    for child in children.iter() {
+                        let result = is_node_the_searched_function(child, method);
+                        if result.is_some() {
+                            return result;
+                        }
+                    }
                 }
             }
         }
@@ -195,6 +569,21 @@ impl AppMap {
         }
         None
     }
+    /// Adds the `labels` the function does not have yet.
+    pub fn add_function_labels(&mut self, class: &str, method: &str, labels: &[String]) {
+        if labels.is_empty() {
+            return;
+        }
+        let Some(CodeObjectType::Function(function)) = self.find_in_class_map_mut(class, method)
+        else {
+            return;
+        };
+        for label in labels {
+            if !function.labels.iter().flatten().any(|x| x == label) {
+                function.labels.push_or_create(label.clone());
+            }
+        }
+    }
     fn find_in_class_map_mut(&mut self, class: &str, method: &str) -> Option<&mut CodeObjectType> {
         for node in self.data.class_map.iter_mut() {
             let class_node = find_class_in_tree_mut(node, class);
@@ -211,14 +600,23 @@ impl AppMap {
         }
         None
     }
-    pub fn write_to_file(&self) -> Result<(), Box<dyn Error>> {
+    /// Writes the map to `path`, creating its directory. The map is written to a temporary file
+    /// first and moved into place, so an existing map is never left half-written.
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
         let s = serde_json::to_string_pretty(self)?;
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
         let mut file = File::options()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(Path::new("maps/tmp/test_1.appmap.json"))?;
+            .open(&temp_path)?;
         file.write_all(s.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(temp_path, path)?;
 
         Ok(())
     }
@@ -226,51 +624,253 @@ impl AppMap {
 
 impl<S: Subscriber + Debug + for<'lookup> LookupSpan<'lookup>> Layer<S> for AppMapLayer {
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
-        println!("event: {:?}; ctx: {:?}", event, ctx);
+        if !event.fields().any(|x| x.name() == RETURN_FIELD) {
+            return;
+        }
+        if let Some(span) = ctx.event_span(event) {
+            if let Some(call) = span.extensions_mut().get_mut::<RecordedCall>() {
+                call.return_value = self.return_value(event);
+            }
+        }
     }
     fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
-        println!("on_enter=> id: {:?}; ctx: {:?}", id, ctx);
-        let metadata = ctx.metadata(id);
-        if let Some(metadata) = metadata {
-            let parameters = metadata.fields();
-            println!("parameters: {:?}", parameters);
-            for field in parameters {
-                println!("field: {:?}", field);
-                // let x = field.value();
-                // println!("field value: {:?}", x);
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let thread_id = current_thread_id();
+        let mut extensions = span.extensions_mut();
+        if let Some(call) = extensions.get_mut::<RecordedCall>() {
+            // spans of async functions are entered again on every poll
+            call.entered_on = Some(thread_id);
+            return;
+        }
+        if extensions.get_mut::<Unsampled>() == Some(&mut Unsampled::BelowCap) {
+            extensions.replace(Unsampled::Counted);
+            drop(extensions);
+            self.add_unrecorded_call(&span);
+            return;
+        }
+        let Some(included) = extensions.get_mut::<Included>() else {
+            return;
+        };
+        let metadata = span.metadata();
+        if !self.sampler.record_call(metadata) {
+            extensions.remove::<Included>();
+            extensions.insert(Unsampled::Counted);
+            drop(extensions);
+            self.add_unrecorded_call(&span);
+            return;
+        }
+        let mut labels = included.labels.clone();
+        let parameters = Some(included.parameters.clone()).filter(|x| !x.is_empty());
+        let follows_from = included.follows_from.clone();
+        drop(extensions);
+        for label in self.label_rules.labels_for(metadata) {
+            if !labels.iter().any(|x| x == label) {
+                labels.push(label.to_string());
             }
-            let x: Option<Field> = parameters.field("x");
-            let x2 = self.in_current_span();
-
-            println!("some test data: (1) {:?}", x);
-            let x = x2.span().field("x");
-            // let x = metadata.callsite();
-            println!("some test data: (2) {:?}", x);
-
-            self.test.lock().unwrap().add_function_call_event(
-                9999,
+        }
+        // A span whose parent is not entered on this thread runs in a task the parent spawned.
+        // The parent may have been recorded on another thread, as tasks move between threads.
+        let spawned_from = span
+            .scope()
+            .skip(1)
+            .find_map(|x| {
+                let extensions = x.extensions();
+                let call = extensions.get::<RecordedCall>()?;
+                Some((call.entered_on, call.event_ids().collect::<Vec<_>>()))
+            })
+            .filter(|(entered_on, _)| *entered_on != Some(thread_id));
+        let follows_from: Vec<(u64, EventId)> = follows_from
+            .iter()
+            .filter_map(|x| ctx.span(x))
+            .filter_map(|x| {
+                let extensions = x.extensions();
+                Some(
+                    extensions
+                        .get::<RecordedCall>()?
+                        .event_ids()
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect();
+        // the causes of the call in the map with this recording id, 0 being the default map
+        let causes = |app_map: &mut AppMap, recording_id: u64| {
+            let mut caused_by = vec![];
+            if let Some((entered_on, parent_ids)) = spawned_from.as_ref() {
+                if let Some((_, parent_id)) = parent_ids.iter().find(|(x, _)| *x == recording_id) {
+                    caused_by.extend(match entered_on {
+                        // the parent runs on another thread right now, so the spawn is
+                        // recorded inside of it
+                        Some(parent_thread_id) => app_map.add_spawn_event(
+                            *parent_thread_id,
+                            metadata.target(),
+                            metadata.name(),
+                        ),
+                        // the parent is suspended, so only its call is the cause
+                        None => Some(*parent_id),
+                    });
+                }
+            }
+            caused_by.extend(
+                follows_from
+                    .iter()
+                    .filter(|(x, _)| *x == recording_id)
+                    .map(|(_, event_id)| *event_id),
+            );
+            caused_by
+        };
+        let add_call = |app_map: &mut AppMap, recording_id: u64| {
+            let caused_by = causes(app_map, recording_id);
+            let event_id = app_map.add_function_call_event(
+                thread_id,
                 metadata.target().to_string(),
                 metadata.name().to_string(),
-                metadata.file().map(|f| PathBuf::from(f)),
+                metadata.file().map(PathBuf::from),
                 metadata.line().map(|x| x as usize),
                 true,
+                parameters.clone(),
             );
-            self.test.lock().unwrap().write_to_file().unwrap();
+            app_map.add_function_labels(metadata.target(), metadata.name(), &labels);
+            app_map.set_caused_by(event_id, caused_by);
+            event_id
+        };
+        let mut app_map = self.test.lock().unwrap();
+        let event_id = add_call(&mut app_map, 0);
+        self.flush_events(&mut app_map);
+        let mut rotated_parts = app_map.take_rotated_parts();
+        drop(app_map);
+
+        let span_scope: Vec<Id> = span.scope().map(|x| x.id()).collect();
+        let mut recordings = self.recordings.lock().unwrap();
+        let scoped_event_ids = recordings
+            .matching(&span_scope)
+            .map(|recording| (recording.id, add_call(&mut recording.app_map, recording.id)))
+            .collect();
+        rotated_parts.append(&mut recordings.take_rotated_parts());
+        drop(recordings);
+        rotated_parts.iter().for_each(RotatedPart::write);
+        span.extensions_mut().insert(RecordedCall {
+            event_id,
+            scoped_event_ids,
+            thread_id,
+            entered_on: Some(thread_id),
+            start: Instant::now(),
+            return_value: None,
+        });
+    }
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(call) = span.extensions_mut().get_mut::<RecordedCall>() {
+                call.entered_on = None;
+            }
+        }
+    }
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(included) = span.extensions_mut().get_mut::<Included>() {
+                included.follows_from.push(follows.clone());
+            }
         }
     }
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
-        println!("on_close=> id: {:?}; ctx: {:?}", id, ctx);
+        if let Some(span) = ctx.span(&id) {
+            let extensions = span.extensions();
+            if let Some(call) = extensions.get::<RecordedCall>() {
+                let data = match call.return_value.clone() {
+                    Some(return_value) => ReturnObjectType::Function(FunctionReturnObject {
+                        return_value: Some(return_value),
+                        exceptions: None,
+                    }),
+                    None => ReturnObjectType::Normal,
+                };
+                self.record_return(call, data);
+            }
+        }
+        self.recordings.lock().unwrap().close_span(&id);
     }
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
-        println!(
-            "on_new_span(self: {{...}}, attrs: {:?}, id: {:?}, ctx: {:?}",
-            attrs, id, ctx
-        );
-        println!("values: {:?}", attrs.values());
+        if !self.filter.is_recorded(attrs.metadata()) {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            // decided by the nearest parent that passed the filter, so sampled trees stay whole
+            let unsampled = span
+                .scope()
+                .skip(1)
+                .find_map(|x| {
+                    let extensions = x.extensions();
+                    match extensions.get::<Unsampled>() {
+                        Some(Unsampled::Tree) => Some(Some(Unsampled::Tree)),
+                        Some(_) => Some(Some(Unsampled::BelowCap)),
+                        None => extensions.get::<Included>().map(|_| None),
+                    }
+                })
+                .unwrap_or_else(|| (!self.sampler.sample_root()).then_some(Unsampled::Tree));
+            if let Some(unsampled) = unsampled {
+                span.extensions_mut().insert(unsampled);
+                return;
+            }
+            let mut included = Included {
+                labels: vec![],
+                parameters: vec![],
+                follows_from: vec![],
+            };
+            self.record_fields(&mut included, |x| attrs.record(x));
+            span.extensions_mut().insert(included);
+        }
+    }
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(included) = extensions.get_mut::<Included>() else {
+            return;
+        };
+        self.record_fields(included, |x| values.record(x));
+        let labels = included.labels.clone();
+        if let Some(call) = extensions.get_mut::<RecordedCall>() {
+            // the function of the call is already in the class map
+            self.add_labels(call, span.metadata(), &labels);
+        }
     }
-    fn on_record(&self, _span: &Id, _values: &Record<'_>, _ctx: Context<'_, S>) {
-        println!("span: {:?}, values: {:?}, ctx: {:?}", _span, _values, _ctx);
+}
+
+/// Adds the return event of `call` to the scoped recordings it is part of.
+fn add_scoped_returns(
+    recordings: &mut Recordings,
+    call: &RecordedCall,
+    elapsed: Option<f64>,
+    data: &ReturnObjectType,
+) {
+    for (recording_id, event_id) in call.scoped_event_ids.iter() {
+        if let Some(recording) = recordings.get_mut(*recording_id) {
+            recording.app_map.add_function_return_event(
+                call.thread_id,
+                *event_id,
+                elapsed,
+                data.clone(),
+            );
+        }
+    }
+}
+
+fn now_timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// A small number identifying the current thread, starting at 1 for the first recorded thread.
+fn current_thread_id() -> u32 {
+    static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+    thread_local! {
+        static THREAD_ID: u32 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
     }
+    THREAD_ID.with(|x| *x)
 }
 
 // #[derive(Debug, Clone)]
@@ -285,3 +885,4 @@ impl<S: Subscriber + Debug + for<'lookup> LookupSpan<'lookup>> Layer<S> for AppM
 // }
 mod extensions;
 mod node_functions;
+mod parameters;
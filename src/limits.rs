@@ -0,0 +1,284 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::appmap_definition::*;
+use crate::AppMap;
+
+/// What happens to new events once a map holds as many events as its [EventLimits] allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    ///Drop the oldest events, keeping the map between 90% and 100% of the limits.
+    DropOldest,
+    ///Drop new calls until the recording ends.
+    DropNewest,
+    ///Write the finished calls to a new file `part_<n>.appmap.json` in `directory` and
+    /// continue with the calls which did not return yet. These do not count against the limits
+    /// until the next rotation. The parts of a scoped recording are written to the
+    /// subdirectory `recording_<id>`.
+    Rotate { directory: PathBuf },
+}
+
+/// Bounds the memory of an always-on recording. See [crate::AppMapLayer::with_limits].
+///
+/// Call and return events are always dropped together: a return is kept as long as its call is,
+/// and the return of a dropped call is dropped as well. The number of dropped events is recorded
+/// as `dropped_events` in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLimits {
+    pub max_events: Option<usize>,
+    ///Maximum size of the events serialized as compact JSON.
+    pub max_bytes: Option<usize>,
+    pub policy: OverflowPolicy,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LimitState {
+    limits: Option<EventLimits>,
+    ///Serialized size of the stored events, if `max_bytes` is set.
+    bytes: usize,
+    ///Calls which were dropped while their return is still to come.
+    dropped_calls: HashSet<EventId>,
+    dropped_events: u64,
+    rotations: u32,
+    ///Number and size of the events of calls which were still open at the last rotation.
+    kept_events: usize,
+    kept_bytes: usize,
+    ///Parts which were rotated out but not written yet.
+    rotated_parts: Vec<RotatedPart>,
+}
+
+/// A part of a rotated map, written once the map is no longer locked.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RotatedPart {
+    path: PathBuf,
+    data: AppMapObject,
+}
+
+impl RotatedPart {
+    pub fn write(&self) {
+        let written = match self.path.parent() {
+            Some(directory) => std::fs::create_dir_all(directory).map_err(|e| e.into()),
+            None => Ok(()),
+        }
+        .and_then(|_| self.data.write_to_file(&self.path));
+        if let Err(e) = written {
+            warn!("could not write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl LimitState {
+    pub fn new(limits: EventLimits) -> Self {
+        Self {
+            limits: Some(limits),
+            ..Default::default()
+        }
+    }
+}
+
+impl EventLimits {
+    fn is_exceeded(&self, events: usize, bytes: usize) -> bool {
+        self.max_events.is_some_and(|x| events > x) || self.max_bytes.is_some_and(|x| bytes > x)
+    }
+
+    /// The limits of the scoped recording with the id `recording_id`.
+    pub(crate) fn for_recording(&self, recording_id: u64) -> Self {
+        let mut limits = self.clone();
+        if let OverflowPolicy::Rotate { directory } = &mut limits.policy {
+            *directory = directory.join(format!("recording_{}", recording_id));
+        }
+        limits
+    }
+}
+
+impl AppMap {
+    /// Limits the number of events kept by this map. With [OverflowPolicy::Rotate], the parts
+    /// are written by [AppMap::write_rotated_parts].
+    pub fn set_limits(&mut self, limits: EventLimits) {
+        self.limit_state = LimitState::new(limits);
+    }
+
+    /// Whether the call with the id `event_id` was dropped before its return was added.
+    pub(crate) fn is_dropped(&self, event_id: EventId) -> bool {
+        self.limit_state.dropped_calls.contains(&event_id)
+    }
+
+    /// Adds `event`, following the limits of the map.
+    pub(crate) fn push_event(&mut self, event: EventObject) {
+        match self.limit_state.limits.take() {
+            Some(limits) => {
+                self.push_limited_event(event, &limits);
+                self.limit_state.limits = Some(limits);
+            }
+            None => self.data.events.push(event),
+        }
+    }
+
+    /// Takes the parts rotated out since the last call, which the caller writes with
+    /// [RotatedPart::write] once it released the map.
+    pub(crate) fn take_rotated_parts(&mut self) -> Vec<RotatedPart> {
+        std::mem::take(&mut self.limit_state.rotated_parts)
+    }
+
+    /// Writes the parts rotated out since the last call. A layer writes the parts of its
+    /// recordings itself, after releasing them.
+    pub fn write_rotated_parts(&mut self) {
+        self.take_rotated_parts()
+            .iter()
+            .for_each(RotatedPart::write);
+    }
+
+    fn push_limited_event(&mut self, event: EventObject, limits: &EventLimits) {
+        if let EventObjectType::Return(ret) = &event.event {
+            if self.limit_state.dropped_calls.remove(&ret.parent_id) {
+                self.count_dropped(1);
+                return;
+            }
+        }
+        let size = if limits.max_bytes.is_some() {
+            serialized_size(&event)
+        } else {
+            0
+        };
+        let exceeded = limits.is_exceeded(
+            (self.data.events.len() + 1).saturating_sub(self.limit_state.kept_events),
+            (self.limit_state.bytes + size).saturating_sub(self.limit_state.kept_bytes),
+        );
+        if exceeded {
+            match &limits.policy {
+                OverflowPolicy::DropNewest => {
+                    if let EventObjectType::Call(_) = event.event {
+                        self.limit_state.dropped_calls.insert(event.id);
+                        self.count_dropped(1);
+                        return;
+                    }
+                }
+                OverflowPolicy::DropOldest => {}
+                OverflowPolicy::Rotate { directory } => self.rotate(directory, limits),
+            }
+        }
+        self.data.events.push(event);
+        self.limit_state.bytes += size;
+        if exceeded && limits.policy == OverflowPolicy::DropOldest {
+            self.drop_oldest(limits);
+        }
+    }
+
+    /// Removes all events, e.g. to write them to a stream.
+    pub(crate) fn take_events(&mut self) -> Vec<EventObject> {
+        self.limit_state.bytes = 0;
+        self.limit_state.kept_events = 0;
+        self.limit_state.kept_bytes = 0;
+        std::mem::take(&mut self.data.events)
+    }
+
+    fn count_dropped(&mut self, count: u64) {
+        self.limit_state.dropped_events += count;
+        self.data
+            .metadata
+            .get_or_insert_with(Default::default)
+            .dropped_events = Some(self.limit_state.dropped_events);
+    }
+
+    fn drop_oldest(&mut self, limits: &EventLimits) {
+        let target_events = limits.max_events.map(|x| x - x / 10);
+        let target_bytes = limits.max_bytes.map(|x| x - x / 10);
+        let mut events = self.data.events.len();
+        let mut bytes = self.limit_state.bytes;
+        let mut prefix = 0;
+        while prefix < self.data.events.len()
+            && (target_events.is_some_and(|x| events > x)
+                || target_bytes.is_some_and(|x| bytes > x))
+        {
+            if target_bytes.is_some() {
+                bytes -= serialized_size(&self.data.events[prefix]);
+            }
+            events -= 1;
+            prefix += 1;
+        }
+        let mut dropped_calls: HashSet<EventId> = self.data.events[..prefix]
+            .iter()
+            .filter(|x| matches!(x.event, EventObjectType::Call(_)))
+            .map(|x| x.id)
+            .collect();
+        for event in self.data.events[..prefix].iter() {
+            if let EventObjectType::Return(ret) = &event.event {
+                dropped_calls.remove(&ret.parent_id);
+            }
+        }
+        let mut dropped = prefix as u64;
+        self.data.events.drain(..prefix);
+        self.data.events.retain(|x| match &x.event {
+            EventObjectType::Return(ret) if dropped_calls.remove(&ret.parent_id) => {
+                if target_bytes.is_some() {
+                    bytes -= serialized_size(x);
+                }
+                dropped += 1;
+                false
+            }
+            _ => true,
+        });
+        self.limit_state.bytes = bytes;
+        self.limit_state.dropped_calls.extend(dropped_calls);
+        self.count_dropped(dropped);
+    }
+
+    /// Moves the finished calls to the next part, see [AppMap::take_rotated_parts]. Calls which
+    /// did not return yet stay in the map.
+    fn rotate(&mut self, directory: &Path, limits: &EventLimits) {
+        let returned: HashSet<EventId> = self
+            .data
+            .events
+            .iter()
+            .filter_map(|x| match &x.event {
+                EventObjectType::Return(ret) => Some(ret.parent_id),
+                _ => None,
+            })
+            .collect();
+        let (finished, open): (Vec<_>, Vec<_>) =
+            self.data.events.drain(..).partition(|x| match x.event {
+                EventObjectType::Call(_) => returned.contains(&x.id),
+                EventObjectType::Return(_) => true,
+            });
+        self.data.events = open;
+        self.limit_state.bytes = match limits.max_bytes {
+            Some(_) => self.data.events.iter().map(serialized_size).sum(),
+            None => 0,
+        };
+        self.limit_state.kept_events = self.data.events.len();
+        self.limit_state.kept_bytes = self.limit_state.bytes;
+        if finished.is_empty() {
+            return;
+        }
+        self.limit_state.rotations += 1;
+        let data = AppMapObject {
+            version: self.data.version.clone(),
+            metadata: self.data.metadata.clone(),
+            class_map: self.data.class_map.clone(),
+            events: finished,
+            event_updates: None,
+        };
+        let path = directory.join(format!("part_{}.appmap.json", self.limit_state.rotations));
+        self.limit_state
+            .rotated_parts
+            .push(RotatedPart { path, data });
+    }
+}
+
+fn serialized_size(event: &EventObject) -> usize {
+    struct ByteCounter(usize);
+    impl Write for ByteCounter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, event).map_or(0, |_| counter.0)
+}
@@ -1,23 +1,423 @@
 use std::error::Error;
 use std::fmt::Error as FmtError;
 use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use tracing::{info, instrument};
 
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::Registry;
 
+use appmap_tracing_test::appmap_definition::diff::DiffOptions;
+use appmap_tracing_test::appmap_definition::findings::{FindingsOptions, FindingsReport};
+use appmap_tracing_test::appmap_definition::merge::MergeOptions;
+use appmap_tracing_test::appmap_definition::prune::PruneOptions;
+use appmap_tracing_test::appmap_definition::rules::{RuleSet, Severity};
+use appmap_tracing_test::appmap_definition::source_info::{SourceCache, SourceOptions};
 use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::config::AppMapConfig;
+use appmap_tracing_test::convert::chrome_trace::{
+    read_chrome_trace, to_chrome_trace, ChromeTraceOptions, ChromeTracePhase,
+};
+use appmap_tracing_test::convert::folded::{to_folded, FoldedOptions, FoldedWeight};
+use appmap_tracing_test::convert::otlp::{to_otlp, OtlpOptions};
+use appmap_tracing_test::convert::sequence_diagram::{
+    to_sequence_diagram, ActorGrouping, SequenceDiagramOptions,
+};
+use appmap_tracing_test::convert::tracing_json::read_tracing_json;
+use appmap_tracing_test::streaming::{repair, RepairOutcome};
 use appmap_tracing_test::*;
-use reqwest;
+
+#[derive(Debug, Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print call counts and timings per function of a recorded AppMap
+    Stats {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Remove frequent and repetitive calls from a recorded AppMap
+    Prune {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        #[arg(long)]
+        max_leaf_calls: Option<usize>,
+        #[arg(long)]
+        max_repeated_subtrees: Option<usize>,
+        /// Target size of the pruned map in bytes
+        #[arg(long)]
+        max_size: Option<usize>,
+    },
+    /// Combine several recorded AppMaps into one
+    Merge {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Order the events of all maps by their timestamp
+        #[arg(long)]
+        order_by_timestamp: bool,
+    },
+    /// Compare the functions, calls and timings of two recorded AppMaps
+    Diff {
+        base: PathBuf,
+        head: PathBuf,
+        /// Relative slowdown of a function's mean elapsed time that is reported as a regression
+        #[arg(long, default_value_t = 0.2)]
+        elapsed_threshold: f64,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Report N+1 queries, repeated HTTP requests, repeated calls and violations of custom rules
+    /// as AppMap scanner findings
+    Findings {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[arg(short, long, default_value = "appmap-findings.json")]
+        output: PathBuf,
+        #[arg(long, default_value_t = FindingsOptions::default().min_query_repeats)]
+        min_query_repeats: usize,
+        #[arg(long, default_value_t = FindingsOptions::default().min_request_repeats)]
+        min_request_repeats: usize,
+        #[arg(long, default_value_t = FindingsOptions::default().max_function_calls)]
+        max_function_calls: usize,
+        /// YAML file with custom rules to check, e.g. appmap-rules.yml
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        /// Fail if a rule is violated with this severity or a higher one
+        #[arg(long, value_enum)]
+        fail_on: Option<SeverityArg>,
+    },
+    /// Convert a recorded AppMap into another format, or a log into an AppMap
+    Convert {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = ConvertSource::Appmap)]
+        from: ConvertSource,
+        #[arg(long, value_enum, default_value_t = ConvertFormat::Appmap)]
+        to: ConvertFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// What the folded stacks are weighted by
+        #[arg(long, value_enum, default_value_t = FoldedWeightArg::SelfTime)]
+        weight: FoldedWeightArg,
+        /// Start every folded stack with the thread it ran on
+        #[arg(long)]
+        per_thread: bool,
+        /// How the calls are written as Chrome trace events
+        #[arg(long, value_enum, default_value_t = ChromeTracePhaseArg::Complete)]
+        phase: ChromeTracePhaseArg,
+        /// What the participants of sequence diagrams are
+        #[arg(long, value_enum, default_value_t = ActorGroupingArg::Class)]
+        group_by: ActorGroupingArg,
+    },
+    /// Complete a streamed AppMap whose recording was never finished
+    Repair { file: PathBuf },
+    /// Add the doc comments and source code of the recorded functions from their source files
+    AddSource {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Directory the paths of the functions are relative to
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+        #[arg(long)]
+        no_comment: bool,
+        #[arg(long)]
+        no_source: bool,
+        /// Keep locations pointing at the `#[instrument]` attribute instead of the `fn` line
+        #[arg(long)]
+        keep_location: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ConvertSource {
+    /// AppMap JSON
+    Appmap,
+    /// Log of `tracing_subscriber::fmt().json()` with span events
+    TracingJson,
+    /// Chrome Trace Event Format, e.g. written by tracing-chrome
+    ChromeTrace,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ConvertFormat {
+    /// AppMap JSON
+    Appmap,
+    /// Chrome Trace Event Format, for chrome://tracing and Perfetto
+    ChromeTrace,
+    /// PlantUML sequence diagram
+    Plantuml,
+    /// Mermaid sequence diagram
+    Mermaid,
+    /// Folded stacks for inferno-flamegraph and flamegraph.pl
+    Folded,
+    /// OpenTelemetry OTLP JSON traces, for the file or HTTP receiver of a collector
+    OtlpJson,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SeverityArg {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ChromeTracePhaseArg {
+    /// One "X" event per call
+    Complete,
+    /// A "B" and an "E" event per call
+    BeginEnd,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ActorGroupingArg {
+    /// One participant per class
+    Class,
+    /// One participant per package, or per top level module if the class map has no packages
+    Package,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum FoldedWeightArg {
+    /// Self time in microseconds
+    SelfTime,
+    /// Number of calls
+    Calls,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    #[value(alias = "table")]
+    Text,
+    Json,
+}
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
-    init_tracing();
+    let cli = Cli::parse();
+    match cli.command {
+        None => run_sample().await,
+        Some(Command::Stats { file, format }) => {
+            let stats = AppMapObject::read_from_file(file)?.stats();
+            match format {
+                OutputFormat::Text => print!("{}", stats),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+            }
+            Ok(())
+        }
+        Some(Command::Prune {
+            file,
+            output,
+            max_leaf_calls,
+            max_repeated_subtrees,
+            max_size,
+        }) => {
+            let mut data = AppMapObject::read_from_file(file)?;
+            data.prune(&PruneOptions {
+                max_leaf_calls,
+                max_repeated_subtrees,
+                max_size,
+            })?;
+            data.write_to_file(output)
+        }
+        Some(Command::Merge {
+            files,
+            output,
+            order_by_timestamp,
+        }) => {
+            let maps = files
+                .iter()
+                .map(AppMapObject::read_from_file)
+                .collect::<Result<Vec<_>, _>>()?;
+            let data = AppMapObject::merge(maps, &MergeOptions { order_by_timestamp });
+            data.write_to_file(output)
+        }
+        Some(Command::Diff {
+            base,
+            head,
+            elapsed_threshold,
+            format,
+        }) => {
+            let base = AppMapObject::read_from_file(base)?;
+            let head = AppMapObject::read_from_file(head)?;
+            let diff = base.diff(&head, &DiffOptions { elapsed_threshold });
+            match format {
+                OutputFormat::Text => print!("{}", diff),
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+            }
+            Ok(())
+        }
+        Some(Command::Findings {
+            files,
+            output,
+            min_query_repeats,
+            min_request_repeats,
+            max_function_calls,
+            rules,
+            fail_on,
+        }) => {
+            let options = FindingsOptions {
+                min_query_repeats,
+                min_request_repeats,
+                max_function_calls,
+            };
+            let rules = rules.map(RuleSet::load).transpose()?;
+            let mut report = FindingsReport::new();
+            if let Some(rules) = rules.as_ref() {
+                report.add_rules(rules);
+            }
+            for file in files {
+                let data = AppMapObject::read_from_file(&file)?;
+                report.add(&file.to_string_lossy(), &data, &options);
+                if let Some(rules) = rules.as_ref() {
+                    report.add_rule_findings(&file.to_string_lossy(), &data, rules);
+                }
+            }
+            for finding in report.findings.iter() {
+                println!("{}", finding);
+            }
+            write_output(Some(output), &serde_json::to_string_pretty(&report)?)?;
+
+            let Some(fail_on) = fail_on else {
+                return Ok(());
+            };
+            let fail_on = match fail_on {
+                SeverityArg::Info => Severity::Info,
+                SeverityArg::Warning => Severity::Warning,
+                SeverityArg::Error => Severity::Error,
+            };
+            let failed = report
+                .findings
+                .iter()
+                .filter(|x| x.severity.is_some_and(|x| x >= fail_on))
+                .count();
+            if failed > 0 {
+                return Err(format!(
+                    "{} rule violations with severity {} or higher",
+                    failed, fail_on
+                )
+                .into());
+            }
+            Ok(())
+        }
+        Some(Command::Convert {
+            file,
+            from,
+            to,
+            output,
+            weight,
+            per_thread,
+            phase,
+            group_by,
+        }) => {
+            let data = match from {
+                ConvertSource::Appmap => AppMapObject::read_from_file(file)?,
+                ConvertSource::TracingJson => read_tracing_json(file)?,
+                ConvertSource::ChromeTrace => read_chrome_trace(file)?,
+            };
+            let converted = match to {
+                ConvertFormat::Appmap => serde_json::to_string_pretty(&data)?,
+                ConvertFormat::ChromeTrace => {
+                    let phase = match phase {
+                        ChromeTracePhaseArg::Complete => ChromeTracePhase::Complete,
+                        ChromeTracePhaseArg::BeginEnd => ChromeTracePhase::BeginEnd,
+                    };
+                    let options = ChromeTraceOptions {
+                        phase,
+                        ..Default::default()
+                    };
+                    serde_json::to_string(&to_chrome_trace(&data, &options))?
+                }
+                ConvertFormat::Plantuml => {
+                    to_sequence_diagram(&data, &sequence_diagram_options(group_by)).to_plantuml()
+                }
+                ConvertFormat::Mermaid => {
+                    to_sequence_diagram(&data, &sequence_diagram_options(group_by)).to_mermaid()
+                }
+                ConvertFormat::Folded => {
+                    let weight = match weight {
+                        FoldedWeightArg::SelfTime => FoldedWeight::SelfTime,
+                        FoldedWeightArg::Calls => FoldedWeight::Calls,
+                    };
+                    to_folded(&data, &FoldedOptions { weight, per_thread })
+                }
+                ConvertFormat::OtlpJson => {
+                    serde_json::to_string(&to_otlp(&data, &OtlpOptions::default()))?
+                }
+            };
+            write_output(output, &converted)
+        }
+        Some(Command::Repair { file }) => {
+            match repair(&file)? {
+                RepairOutcome::AlreadyComplete => {
+                    println!("{} is already complete", file.display())
+                }
+                RepairOutcome::Repaired { recovered_events } => {
+                    println!(
+                        "recovered {} events in {}",
+                        recovered_events,
+                        file.display()
+                    )
+                }
+            }
+            Ok(())
+        }
+        Some(Command::AddSource {
+            file,
+            output,
+            root,
+            no_comment,
+            no_source,
+            keep_location,
+        }) => {
+            let mut data = AppMapObject::read_from_file(file)?;
+            data.add_source_info(
+                &mut SourceCache::new(root),
+                &SourceOptions {
+                    comment: !no_comment,
+                    source: !no_source,
+                    fix_location: !keep_location,
+                },
+            );
+            data.write_to_file(output)
+        }
+    }
+}
+
+fn sequence_diagram_options(group_by: ActorGroupingArg) -> SequenceDiagramOptions {
+    let grouping = match group_by {
+        ActorGroupingArg::Class => ActorGrouping::Class,
+        ActorGroupingArg::Package => ActorGrouping::Package,
+    };
+    SequenceDiagramOptions { grouping }
+}
+
+fn write_output(output: Option<PathBuf>, content: &str) -> Result<(), Box<dyn Error>> {
+    match output {
+        Some(path) => std::fs::write(path, content)?,
+        None if content.ends_with('\n') => print!("{}", content),
+        None => println!("{}", content),
+    }
+    Ok(())
+}
+
+async fn run_sample() -> Result<(), Box<dyn Error>> {
+    let app_layer = init_tracing();
 
     sample_json()?;
     test_sub_mod();
     test_reqwest(true).await?;
-    Ok(())
+    app_layer.finish()
 }
 
 #[instrument]
@@ -27,17 +427,25 @@ async fn test_reqwest(test_param: bool) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn init_tracing() {
+fn init_tracing() -> AppMapLayer {
     // let stdout_layer = tracing_subscriber::fmt::layer().pretty();
-    let app_layer = AppMapLayer::new();
+    let config = AppMapConfig::find().expect("Unable to read appmap.yml");
+    let app_layer = AppMapLayer::new()
+        .with_config(&config)
+        .expect("Invalid appmap.yml");
 
     let subscriber = Registry::default()
         //
         // .with(stdout_layer)
         //
-        .with(app_layer);
+        .with(app_layer.clone());
 
     tracing::subscriber::set_global_default(subscriber).expect("Unable to set global subscriber");
+    app_layer.install_panic_hook();
+    app_layer
+        .finish_on_termination()
+        .expect("Unable to handle termination signals");
+    app_layer
 }
 
 //region AppMapObject
@@ -61,12 +469,14 @@ fn sample_json() -> Result<(), Box<dyn Error>> {
                     labels: Some(vec!["security".to_string()]),
                     comment: None,
                     source: None,
+                    unrecorded_calls: None,
                 })]),
             })]),
         })],
         events: vec![EventObject {
             id: EventId::from(1),
             thread_id: 9999,
+            timestamp: None,
             event: EventObjectType::Call(CallObject {
                 defined_class: "main".to_string(),
                 method_id: "sample_json".to_string(),
@@ -75,6 +485,7 @@ fn sample_json() -> Result<(), Box<dyn Error>> {
                 receiver: None,
                 parameters: None,
                 is_static: true,
+                caused_by: None,
                 type_: CallObjectType::Function,
             }),
         }],
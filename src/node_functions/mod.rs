@@ -1,4 +1,5 @@
 use crate::appmap_definition::*;
+use crate::extensions::OptionVecExtensions;
 
 pub fn find_class_in_tree<'a>(
     node: &'a CodeObjectType,
@@ -25,7 +26,7 @@ pub fn find_class_in_tree<'a>(
             }
         }
     }
-    return None;
+    None
 }
 pub fn is_node_the_searched_function<'a>(
     node: &'a CodeObjectType,
@@ -67,7 +68,7 @@ pub fn find_class_in_tree_mut<'a>(
             }
         }
     }
-    return None;
+    None
 }
 pub fn is_node_the_searched_function_mut<'a>(
     node: &'a mut CodeObjectType,
@@ -83,3 +84,105 @@ pub fn is_node_the_searched_function_mut<'a>(
         _ => None,
     }
 }
+/// Collects every function below `node` together with the `::` separated path of the
+/// packages and classes it is nested in.
+pub fn collect_functions_in_tree<'a>(
+    node: &'a CodeObjectType,
+    parent_path: &str,
+    result: &mut Vec<(String, &'a FunctionCodeObject)>,
+) {
+    let (name, children) = match node {
+        CodeObjectType::Package(p) => (&p.name, p.children.as_ref()),
+        CodeObjectType::Class(c) => (&c.name, c.children.as_ref()),
+        CodeObjectType::Function(f) => {
+            result.push((parent_path.to_string(), f));
+            return;
+        }
+    };
+    let path = if parent_path.is_empty() {
+        name.clone()
+    } else {
+        format!("{}::{}", parent_path, name)
+    };
+    if let Some(children) = children {
+        for child in children.iter() {
+            collect_functions_in_tree(child, &path, result);
+        }
+    }
+}
+/// Removes every function below `node` for which `keep(class_path, function_name)` returns
+/// false. Returns whether `node` itself should be kept, which is not the case for removed
+/// functions and for packages and classes that end up without children.
+pub fn retain_functions_in_tree(
+    node: &mut CodeObjectType,
+    parent_path: &str,
+    keep: &dyn Fn(&str, &str) -> bool,
+) -> bool {
+    let (name, children) = match node {
+        CodeObjectType::Package(p) => (&p.name, &mut p.children),
+        CodeObjectType::Class(c) => (&c.name, &mut c.children),
+        CodeObjectType::Function(f) => return keep(parent_path, &f.name),
+    };
+    let path = if parent_path.is_empty() {
+        name.clone()
+    } else {
+        format!("{}::{}", parent_path, name)
+    };
+    if let Some(list) = children.as_mut() {
+        list.retain_mut(|child| retain_functions_in_tree(child, &path, keep));
+        if list.is_empty() {
+            *children = None;
+        }
+    }
+    children.is_some()
+}
+/// Calls `f` with every function below `node`.
+pub fn for_each_function_in_tree_mut(
+    node: &mut CodeObjectType,
+    f: &mut dyn FnMut(&mut FunctionCodeObject),
+) {
+    let children = match node {
+        CodeObjectType::Package(p) => &mut p.children,
+        CodeObjectType::Class(c) => &mut c.children,
+        CodeObjectType::Function(function) => return f(function),
+    };
+    for child in children.iter_mut().flatten() {
+        for_each_function_in_tree_mut(child, f);
+    }
+}
+/// Adds `node` to `nodes`, merging it into an existing package or class with the same name and
+/// skipping functions that already exist at the same place.
+pub fn merge_into_tree(nodes: &mut Vec<CodeObjectType>, node: CodeObjectType) {
+    let existing = nodes.iter_mut().find(|x| match (&**x, &node) {
+        (CodeObjectType::Package(a), CodeObjectType::Package(b)) => a.name == b.name,
+        (CodeObjectType::Class(a), CodeObjectType::Class(b)) => a.name == b.name,
+        (CodeObjectType::Function(a), CodeObjectType::Function(b)) => a.name == b.name,
+        _ => false,
+    });
+    let Some(existing) = existing else {
+        nodes.push(node);
+        return;
+    };
+    let (existing_children, children) = match (existing, node) {
+        (CodeObjectType::Package(a), CodeObjectType::Package(b)) => (&mut a.children, b.children),
+        (CodeObjectType::Class(a), CodeObjectType::Class(b)) => (&mut a.children, b.children),
+        (CodeObjectType::Function(a), CodeObjectType::Function(b)) => {
+            if let Some(labels) = b.labels {
+                for label in labels {
+                    if !a.labels.iter().flatten().any(|x| *x == label) {
+                        a.labels.push_or_create(label);
+                    }
+                }
+            }
+            if let Some(count) = b.unrecorded_calls {
+                *a.unrecorded_calls.get_or_insert(0) += count;
+            }
+            return;
+        }
+        _ => return,
+    };
+    for child in children.into_iter().flatten() {
+        let list = existing_children.get_or_insert_with(Vec::new);
+        merge_into_tree(list, child);
+    }
+}
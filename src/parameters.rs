@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+
+use serde_json::Value;
+use tracing::field::{Field, Visit};
+
+use crate::appmap_definition::ParameterObject;
+
+/// Collects the fields of a span as parameters of its call. Fields starting with `appmap.` are
+/// settings of the recording and not collected.
+#[derive(Debug, Default)]
+pub(crate) struct ParametersVisitor {
+    pub parameters: Vec<ParameterObject>,
+}
+
+impl ParametersVisitor {
+    fn add(&mut self, field: &Field, class: &str, value: String) {
+        if field.name().starts_with("appmap.") {
+            return;
+        }
+        self.parameters.push(parameter(field.name(), class, value));
+    }
+}
+
+pub(crate) fn parameter(name: &str, class: &str, value: String) -> ParameterObject {
+    ParameterObject {
+        name: Some(name.to_string()),
+        object_id: None,
+        class: class.to_string(),
+        value,
+        size: None,
+        properties: None,
+        items: None,
+    }
+}
+
+/// A parameter with the type class a span field would have had for a JSON value.
+pub(crate) fn json_parameter(name: Option<&str>, value: &Value) -> ParameterObject {
+    let (class, value) = match value {
+        Value::String(x) => ("&str", x.clone()),
+        Value::Bool(x) => ("bool", x.to_string()),
+        Value::Number(x) if x.is_u64() => ("u64", x.to_string()),
+        Value::Number(x) if x.is_i64() => ("i64", x.to_string()),
+        Value::Number(x) => ("f64", x.to_string()),
+        other => ("dyn Debug", other.to_string()),
+    };
+    ParameterObject {
+        name: name.map(|x| x.to_string()),
+        ..parameter("", class, value)
+    }
+}
+
+impl Visit for ParametersVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.add(field, "f64", value.to_string());
+    }
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.add(field, "i64", value.to_string());
+    }
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.add(field, "u64", value.to_string());
+    }
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.add(field, "bool", value.to_string());
+    }
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.add(field, "&str", value.to_string());
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.add(field, "dyn Debug", format!("{:?}", value));
+    }
+}
@@ -0,0 +1,136 @@
+use tracing::Id;
+
+use crate::appmap_definition::*;
+use crate::limits::{EventLimits, RotatedPart};
+use crate::{AppMap, AppMapLayer};
+
+/// Returned by [AppMapLayer::start_recording] and [AppMapLayer::start_recording_in_span].
+///
+/// The recording runs until [RecordingGuard::stop] is called. Dropping the guard without
+/// stopping it discards the recording.
+#[must_use = "the recording is discarded when the guard is dropped"]
+#[derive(Debug)]
+pub struct RecordingGuard {
+    layer: AppMapLayer,
+    id: u64,
+    stopped: bool,
+}
+
+impl RecordingGuard {
+    pub(crate) fn new(layer: AppMapLayer, id: u64) -> Self {
+        Self {
+            layer,
+            id,
+            stopped: false,
+        }
+    }
+    /// Ends the recording and returns everything it recorded.
+    pub fn stop(mut self) -> AppMapObject {
+        self.stopped = true;
+        self.layer
+            .stop_recording(self.id)
+            .expect("the recording is only removed by its guard")
+    }
+}
+
+impl Drop for RecordingGuard {
+    fn drop(&mut self) {
+        if !self.stopped {
+            self.layer.stop_recording(self.id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RecordingScope {
+    ///Every span of the process.
+    All,
+    ///The span with this id and all of its descendants.
+    Span(Id),
+    ///The span to record was disabled or has been closed, so nothing is recorded.
+    Nothing,
+}
+
+#[derive(Debug)]
+pub(crate) struct ScopedRecording {
+    pub id: u64,
+    pub scope: RecordingScope,
+    pub app_map: AppMap,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Recordings {
+    next_id: u64,
+    pub active: Vec<ScopedRecording>,
+    ///Limits of every recording started from now on.
+    pub limits: Option<EventLimits>,
+}
+
+impl Recordings {
+    pub fn start(&mut self, name: String, scope: RecordingScope) -> u64 {
+        self.next_id += 1;
+        let mut app_map = AppMap::new();
+        app_map.data.metadata = Some(MetadataObject {
+            name: Some(name),
+            recorder: Some(RecorderObject {
+                name: "appmap_tracing_test".to_string(),
+                type_: Some(
+                    match scope {
+                        RecordingScope::All => "process",
+                        _ => "requests",
+                    }
+                    .to_string(),
+                ),
+            }),
+            ..Default::default()
+        });
+        if let Some(limits) = self.limits.clone() {
+            app_map.set_limits(limits.for_recording(self.next_id));
+        }
+        self.active.push(ScopedRecording {
+            id: self.next_id,
+            scope,
+            app_map,
+        });
+        self.next_id
+    }
+
+    pub fn stop(&mut self, id: u64) -> Option<AppMapObject> {
+        let index = self.active.iter().position(|x| x.id == id)?;
+        Some(self.active.remove(index).app_map.data)
+    }
+
+    /// The recordings a span belongs to, given the ids of the span and all of its ancestors.
+    pub fn matching<'a>(
+        &'a mut self,
+        span_scope: &'a [Id],
+    ) -> impl Iterator<Item = &'a mut ScopedRecording> + 'a {
+        self.active.iter_mut().filter(|x| match &x.scope {
+            RecordingScope::All => true,
+            RecordingScope::Span(root) => span_scope.contains(root),
+            RecordingScope::Nothing => false,
+        })
+    }
+
+    /// Ends the scope of the recordings of the span `id`, which was closed. The registry reuses
+    /// the ids of closed spans, so a new span with the same id must not be recorded.
+    pub fn close_span(&mut self, id: &Id) {
+        for recording in self.active.iter_mut() {
+            if matches!(&recording.scope, RecordingScope::Span(x) if x == id) {
+                recording.scope = RecordingScope::Nothing;
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut ScopedRecording> {
+        self.active.iter_mut().find(|x| x.id == id)
+    }
+
+    /// Takes the parts rotated out of all recordings, see [AppMap::take_rotated_parts].
+    pub fn take_rotated_parts(&mut self) -> Vec<RotatedPart> {
+        self.active
+            .iter_mut()
+            .flat_map(|x| x.app_map.take_rotated_parts())
+            .collect()
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+use regex::Regex;
+
+use crate::appmap_definition::*;
+use crate::config::RedactionConfig;
+use crate::extensions::OptionVecExtensions;
+use crate::node_functions::{collect_functions_in_tree, for_each_function_in_tree_mut};
+
+/// Replaces every redacted value.
+pub const REDACTED: &str = "[REDACTED]";
+/// Label of the functions that handled a redacted value.
+pub const SECRET_LABEL: &str = "secret";
+
+/// Removes secrets from recorded values before they are stored, following the `redaction`
+/// setting of `appmap.yml`.
+///
+/// A value is replaced by [REDACTED] if its parameter or header name contains one of the
+/// configured names, and every match of the configured value patterns is replaced in the
+/// remaining values. String and number literals of SQL queries are replaced with `?`.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    ///Lower case.
+    names: Vec<String>,
+    values: Vec<Regex>,
+    sql: bool,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self::from_config(&RedactionConfig::default()).expect("the default patterns are valid")
+    }
+}
+
+impl Redactor {
+    pub fn from_config(config: &RedactionConfig) -> Result<Self, Box<dyn Error>> {
+        let values = config
+            .values
+            .iter()
+            .map(|x| Regex::new(x).map_err(|e| format!("invalid redaction pattern {:?}: {}", x, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            names: config.names.iter().map(|x| x.to_lowercase()).collect(),
+            values,
+            sql: config.sql,
+        })
+    }
+
+    /// Redacts nothing.
+    pub fn disabled() -> Self {
+        Self {
+            names: vec![],
+            values: vec![],
+            sql: false,
+        }
+    }
+
+    pub fn is_secret_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.names.iter().any(|x| name.contains(x.as_str()))
+    }
+
+    /// Replaces the secrets in `value`. Returns whether there were any.
+    pub fn redact_value(&self, value: &mut String) -> bool {
+        let mut redacted = false;
+        for pattern in self.values.iter() {
+            if pattern.is_match(value) {
+                *value = pattern.replace_all(value, REDACTED).into_owned();
+                redacted = true;
+            }
+        }
+        redacted
+    }
+
+    /// Returns whether anything was redacted.
+    pub fn redact_parameter(&self, parameter: &mut ParameterObject) -> bool {
+        if parameter
+            .name
+            .as_deref()
+            .is_some_and(|x| self.is_secret_name(x))
+        {
+            parameter.value = REDACTED.to_string();
+            parameter.properties = None;
+            parameter.items = None;
+            return true;
+        }
+        self.redact_value(&mut parameter.value)
+    }
+
+    /// Returns whether anything was redacted.
+    pub fn redact_parameters(&self, parameters: &mut [ParameterObject]) -> bool {
+        let mut redacted = false;
+        for parameter in parameters.iter_mut() {
+            redacted |= self.redact_parameter(parameter);
+        }
+        redacted
+    }
+
+    /// Returns whether anything was redacted.
+    pub fn redact_headers(&self, headers: &mut BTreeMap<String, String>) -> bool {
+        let mut redacted = false;
+        for (name, value) in headers.iter_mut() {
+            if self.is_secret_name(name) {
+                *value = REDACTED.to_string();
+                redacted = true;
+            } else {
+                redacted |= self.redact_value(value);
+            }
+        }
+        redacted
+    }
+
+    /// Replaces the string and number literals of `sql` with `?` if SQL redaction is enabled,
+    /// and the configured secrets in any case. Returns whether there were any secrets, since
+    /// literals are masked in every query.
+    pub fn redact_sql(&self, sql: &mut String) -> bool {
+        let redacted = self.redact_value(sql);
+        if self.sql {
+            *sql = mask_sql_literals(sql);
+        }
+        redacted
+    }
+
+    /// Redacts the parameters, headers, SQL, return value and exception messages of an event.
+    /// Returns whether anything was redacted.
+    pub fn redact_event(&self, event: &mut EventObject) -> bool {
+        let headers = |x: &mut Option<BTreeMap<String, String>>| match x {
+            Some(headers) => self.redact_headers(headers),
+            None => false,
+        };
+        match &mut event.event {
+            EventObjectType::Call(call) => {
+                let mut redacted = match call.parameters.as_mut() {
+                    Some(parameters) => self.redact_parameters(parameters),
+                    None => false,
+                };
+                if let Some(receiver) = call.receiver.as_mut() {
+                    redacted |= self.redact_parameter(receiver);
+                }
+                redacted
+                    | match &mut call.type_ {
+                        CallObjectType::HttpServerRequest(x) => {
+                            let request = &mut x.http_server_request;
+                            let path = self.redact_value(&mut request.path_info);
+                            headers(&mut request.headers) | path
+                        }
+                        CallObjectType::HttpServerResponse(x) => {
+                            headers(&mut x.http_server_response.headers)
+                        }
+                        CallObjectType::HttpClientRequest(x) => {
+                            let request = &mut x.http_client_request;
+                            let url = self.redact_value(&mut request.url);
+                            headers(&mut request.headers) | url
+                        }
+                        CallObjectType::HttpClientResponse(x) => {
+                            headers(&mut x.http_client_response.headers)
+                        }
+                        CallObjectType::SqlQuery(x) => self.redact_sql(&mut x.sql_query.sql),
+                        CallObjectType::Message(x) => self.redact_parameters(&mut x.message),
+                        CallObjectType::Normal | CallObjectType::Function => false,
+                    }
+            }
+            EventObjectType::Return(ret) => match &mut ret.data {
+                ReturnObjectType::Function(x) => {
+                    let mut redacted = match x.return_value.as_mut() {
+                        Some(value) => self.redact_parameter(value),
+                        None => false,
+                    };
+                    for exception in x.exceptions.iter_mut().flatten() {
+                        redacted |= self.redact_value(&mut exception.message);
+                    }
+                    redacted
+                }
+                ReturnObjectType::Exception(x) => {
+                    let mut redacted = false;
+                    for exception in x.exceptions.iter_mut() {
+                        redacted |= self.redact_value(&mut exception.message);
+                    }
+                    redacted
+                }
+                ReturnObjectType::Normal => false,
+            },
+        }
+    }
+}
+
+pub(crate) fn mask_sql_literals(sql: &str) -> String {
+    let mut result = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut previous: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // skip to the closing quote, treating '' as an escaped quote
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            result.push('?');
+            previous = Some('?');
+        } else if c.is_ascii_digit()
+            && !previous.is_some_and(|x| x.is_alphanumeric() || x == '_' || x == '$')
+        {
+            while chars
+                .peek()
+                .is_some_and(|x| x.is_ascii_digit() || *x == '.')
+            {
+                chars.next();
+            }
+            result.push('?');
+            previous = Some('?');
+        } else {
+            result.push(c);
+            previous = Some(c);
+        }
+    }
+    result
+}
+
+impl AppMapObject {
+    /// Redacts every event and adds the [SECRET_LABEL] to the functions whose calls contained a
+    /// secret.
+    pub fn redact(&mut self, redactor: &Redactor) {
+        let mut secret_functions = vec![];
+        let mut calls = HashMap::new();
+        for event in self.events.iter_mut() {
+            if let EventObjectType::Call(call) = &event.event {
+                calls.insert(
+                    event.id,
+                    (call.defined_class.clone(), call.method_id.clone()),
+                );
+            }
+            if redactor.redact_event(event) {
+                let function = match &event.event {
+                    EventObjectType::Call(call) => {
+                        Some((call.defined_class.clone(), call.method_id.clone()))
+                    }
+                    EventObjectType::Return(ret) => calls.get(&ret.parent_id).cloned(),
+                };
+                secret_functions.extend(function);
+            }
+        }
+        if secret_functions.is_empty() {
+            return;
+        }
+        for node in self.class_map.iter_mut() {
+            let mut functions = vec![];
+            collect_functions_in_tree(node, "", &mut functions);
+            // both walks visit the functions in the same order
+            let mut is_secret = functions
+                .iter()
+                .map(|(class, function)| {
+                    secret_functions
+                        .iter()
+                        .any(|(c, m)| c == class && *m == function.name)
+                })
+                .collect::<Vec<_>>()
+                .into_iter();
+            for_each_function_in_tree_mut(node, &mut |function| {
+                let has_label = function.labels.iter().flatten().any(|x| x == SECRET_LABEL);
+                if is_secret.next() == Some(true) && !has_label {
+                    function.labels.push_or_create(SECRET_LABEL.to_string());
+                }
+            });
+        }
+    }
+}
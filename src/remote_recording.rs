@@ -0,0 +1,77 @@
+//! Remote recording as specified by the AppMap protocol, so a running service can be recorded
+//! on demand:
+//!
+//! * `POST /_appmap/record` starts a recording. Responds `409 Conflict` if one is running.
+//! * `GET /_appmap/record` responds `{"enabled": true|false}`.
+//! * `DELETE /_appmap/record` stops the recording and responds with the AppMap. Responds
+//!   `404 Not Found` if no recording is running.
+//!
+//! ```ignore
+//! let layer = AppMapLayer::new();
+//! tracing_subscriber::registry().with(layer.clone()).init();
+//! let app = Router::new()
+//!     .route("/", get(index))
+//!     .merge(appmap_tracing_test::remote_recording::router(layer));
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::recording::RecordingGuard;
+use crate::AppMapLayer;
+
+pub const RECORD_PATH: &str = "/_appmap/record";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RecordingStatus {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone)]
+struct RemoteRecording {
+    layer: AppMapLayer,
+    guard: Arc<Mutex<Option<RecordingGuard>>>,
+}
+
+/// Routes serving [RECORD_PATH], recording with `layer`.
+pub fn router(layer: AppMapLayer) -> Router {
+    let state = RemoteRecording {
+        layer,
+        guard: Arc::new(Mutex::new(None)),
+    };
+    Router::new()
+        .route(RECORD_PATH, get(status).post(start).delete(stop))
+        .with_state(state)
+}
+
+async fn status(State(state): State<RemoteRecording>) -> Json<RecordingStatus> {
+    Json(RecordingStatus {
+        enabled: state.guard.lock().unwrap().is_some(),
+    })
+}
+
+async fn start(State(state): State<RemoteRecording>) -> StatusCode {
+    let mut guard = state.guard.lock().unwrap();
+    if guard.is_some() {
+        return StatusCode::CONFLICT;
+    }
+    *guard = Some(state.layer.start_recording("remote recording"));
+    StatusCode::OK
+}
+
+async fn stop(State(state): State<RemoteRecording>) -> Response {
+    let Some(guard) = state.guard.lock().unwrap().take() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let mut data = guard.stop();
+    if let Some(recorder) = data.metadata.as_mut().and_then(|x| x.recorder.as_mut()) {
+        recorder.type_ = Some("remote".to_string());
+    }
+    Json(data).into_response()
+}
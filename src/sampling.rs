@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use tracing::Metadata;
+
+use crate::appmap_definition::*;
+use crate::config::SamplingConfig;
+use crate::AppMap;
+
+/// Decides how many calls [crate::AppMapLayer] records, following the `sampling` setting of
+/// `appmap.yml`.
+///
+/// Sampling is decided for each root span, i.e. a recorded span without a recorded parent: one
+/// in every `rate` root spans is recorded together with every span below it, all other trees
+/// are not recorded at all. Calls of a function after its first `max_calls_per_function` calls
+/// are not recorded either, nor are the spans below them. These calls are counted as
+/// `unrecorded_calls` of their functions in the class map instead.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    rate: Option<u64>,
+    max_calls_per_function: Option<u64>,
+    roots: AtomicU64,
+    ///Number of calls per function, by target and name. The lock is only written to count the
+    /// first call of a function.
+    calls: RwLock<HashMap<(&'static str, &'static str), AtomicU64>>,
+}
+
+/// Stored in the extensions of every span that passed the [crate::filter::SpanFilter] but is
+/// not recorded because of the [Sampler]. Spans below it are not recorded either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unsampled {
+    ///The tree of the span was not sampled, so its calls are not counted.
+    Tree,
+    ///The span is below a call over the cap of its function. It is counted when it is entered
+    /// for the first time.
+    BelowCap,
+    ///The call of the span was counted as unrecorded call.
+    Counted,
+}
+
+impl Sampler {
+    pub fn new(rate: Option<u64>, max_calls_per_function: Option<u64>) -> Self {
+        Self {
+            rate,
+            max_calls_per_function,
+            ..Default::default()
+        }
+    }
+
+    pub fn from_config(config: &SamplingConfig) -> Result<Self, Box<dyn Error>> {
+        if config.rate == Some(0) {
+            return Err("invalid sampling rate 0, it has to be at least 1".into());
+        }
+        Ok(Self::new(config.rate, config.max_calls_per_function))
+    }
+
+    /// Whether the next root span is recorded. The first root span always is.
+    pub fn sample_root(&self) -> bool {
+        match self.rate {
+            Some(rate) => self
+                .roots
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(rate),
+            None => true,
+        }
+    }
+
+    /// Counts a call of the span with this metadata and returns whether it is below the cap of
+    /// its function.
+    pub fn record_call(&self, metadata: &'static Metadata<'static>) -> bool {
+        let Some(max_calls) = self.max_calls_per_function else {
+            return true;
+        };
+        let key = (metadata.target(), metadata.name());
+        let calls = self.calls.read().unwrap();
+        let count = match calls.get(&key) {
+            Some(count) => count.fetch_add(1, Ordering::Relaxed),
+            None => {
+                drop(calls);
+                let mut calls = self.calls.write().unwrap();
+                calls
+                    .entry(key)
+                    .or_default()
+                    .fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        count < max_calls
+    }
+}
+
+impl AppMap {
+    /// Counts a call of `method` of `class` which was not recorded, adding the function to the
+    /// class map if needed.
+    pub fn add_unrecorded_call(
+        &mut self,
+        class: &str,
+        method: &str,
+        path: Option<&Path>,
+        lineno: Option<usize>,
+    ) {
+        self.add_function_to_class_map(class, method, path, lineno);
+        if let Some(CodeObjectType::Function(function)) = self.find_in_class_map_mut(class, method)
+        {
+            *function.unrecorded_calls.get_or_insert(0) += 1;
+        }
+    }
+}
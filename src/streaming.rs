@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::appmap_definition::*;
+use crate::AppMap;
+
+/// Writes an AppMap to `W` one event at a time, so the events never have to be held in memory.
+///
+/// The header and every event are written on their own line:
+///
+/// ```text
+/// {"version":"1.12","events":[
+/// {"id":1,...}
+/// ,{"id":2,...}
+/// ],"classMap":[...],"metadata":{...}}
+/// ```
+///
+/// Until [AppMapStreamWriter::finish] is called the output is not valid JSON. A file that was
+/// never finished (because the process crashed, for example) can be completed with [repair].
+/// Every event is flushed once it is written, so a crash loses at most the event being written.
+#[derive(Debug)]
+pub struct AppMapStreamWriter<W: Write> {
+    writer: W,
+    written_events: u64,
+}
+
+impl AppMapStreamWriter<File> {
+    pub fn create(path: impl AsRef<Path>, version: &str) -> Result<Self, Box<dyn Error>> {
+        Self::new(File::create(path)?, version)
+    }
+}
+
+impl<W: Write> AppMapStreamWriter<W> {
+    pub fn new(mut writer: W, version: &str) -> Result<Self, Box<dyn Error>> {
+        writeln!(
+            writer,
+            "{{\"version\":{},\"events\":[",
+            serde_json::to_string(version)?
+        )?;
+        Ok(Self {
+            writer,
+            written_events: 0,
+        })
+    }
+
+    /// Appends one event and flushes it.
+    pub fn write_event(&mut self, event: &EventObject) -> Result<(), Box<dyn Error>> {
+        if self.written_events > 0 {
+            self.writer.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut self.writer, event)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        self.written_events += 1;
+        Ok(())
+    }
+
+    /// Flushes the events written so far, which [repair] can then recover.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn written_events(&self) -> u64 {
+        self.written_events
+    }
+
+    /// Closes the events array and writes the remaining parts of the map.
+    pub fn finish(
+        mut self,
+        class_map: &[CodeObjectType],
+        metadata: Option<&MetadataObject>,
+    ) -> Result<W, Box<dyn Error>> {
+        write_footer(&mut self.writer, class_map, metadata)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn write_footer(
+    writer: &mut impl Write,
+    class_map: &[CodeObjectType],
+    metadata: Option<&MetadataObject>,
+) -> Result<(), Box<dyn Error>> {
+    writer.write_all(b"],\"classMap\":")?;
+    serde_json::to_writer(&mut *writer, class_map)?;
+    if let Some(metadata) = metadata {
+        writer.write_all(b",\"metadata\":")?;
+        serde_json::to_writer(&mut *writer, metadata)?;
+    }
+    writer.write_all(b"}\n")?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairOutcome {
+    ///The file was finished properly and has not been changed.
+    AlreadyComplete,
+    ///The file was cut after the last complete event and finished with a class map rebuilt
+    /// from the recovered call events.
+    Repaired { recovered_events: u64 },
+}
+
+/// Completes a file written by [AppMapStreamWriter] that was never finished.
+///
+/// Only one line is held in memory at a time; the class map is rebuilt from the call events
+/// while reading.
+pub fn repair(path: impl AsRef<Path>) -> Result<RepairOutcome, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    serde_json::from_str::<Value>(&format!("{}]}}", line.trim_end()))
+        .map_err(|e| format!("not a streamed AppMap: {}", e))?;
+
+    let mut class_map = AppMap::new();
+    let mut valid_length = line.len() as u64;
+    let mut recovered_events = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.starts_with(']')
+            && serde_json::from_str::<Value>(&format!("{{\"events\":[{}", line)).is_ok()
+        {
+            return Ok(RepairOutcome::AlreadyComplete);
+        }
+        if !line.ends_with('\n') {
+            break;
+        }
+        let Ok(event) = serde_json::from_str::<EventObject>(line.trim_start_matches(',')) else {
+            break;
+        };
+        if let EventObjectType::Call(call) = &event.event {
+            class_map.add_function_to_class_map(
+                &call.defined_class,
+                &call.method_id,
+                call.path.as_deref(),
+                call.lineno,
+            );
+        }
+        valid_length += line.len() as u64;
+        recovered_events += 1;
+    }
+    drop(reader);
+
+    let mut file = OpenOptions::new().write(true).open(path.as_ref())?;
+    file.set_len(valid_length)?;
+    file.seek(SeekFrom::End(0))?;
+    write_footer(&mut file, &class_map.data.class_map, None)?;
+    file.flush()?;
+    Ok(RepairOutcome::Repaired { recovered_events })
+}
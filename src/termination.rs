@@ -0,0 +1,159 @@
+//! Keeps the recording when the process panics or is terminated.
+
+use std::any::Any;
+use std::error::Error;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::warn;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+use crate::appmap_definition::*;
+use crate::recording::Recordings;
+use crate::{add_scoped_returns, AppMap, AppMapLayer, RecordedCall};
+
+/// Exit code of a process ended by Ctrl-C on platforms without signals.
+#[cfg(not(unix))]
+const CTRL_C_EXIT_CODE: i32 = 130;
+
+impl AppMapLayer {
+    /// Installs a panic hook which records the panic and saves the recording so far. The
+    /// previous hook is called afterwards.
+    ///
+    /// Every open span of the panicking thread returns with a "panic" exception. This requires
+    /// the layer to run on top of a [Registry]. The panic also becomes the `exception` of the
+    /// metadata, with its message redacted like the recorded values. As the panic may still be
+    /// caught, the recording is not finished: a streaming layer flushes the events written so
+    /// far, which [repair] can complete, and any other layer writes the map so far. Nothing is
+    /// recorded if the panic happened while the layer was recording on the same thread.
+    ///
+    /// [repair]: crate::streaming::repair
+    pub fn install_panic_hook(&self) {
+        let layer = self.clone();
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Err(e) = layer.save_on_panic(info) {
+                warn!("could not write the AppMap after a panic: {}", e);
+            }
+            previous_hook(info);
+        }));
+    }
+
+    /// Finishes the recording and exits the process when it receives SIGINT (Ctrl-C) or
+    /// SIGTERM, so a recorded service that is stopped still yields a valid map. The exit code
+    /// is 128 plus the number of the signal, as if the process had been killed by it. Spans
+    /// open at that time have no return event.
+    pub fn finish_on_termination(&self) -> Result<(), Box<dyn Error>> {
+        let layer = self.clone();
+        #[cfg(unix)]
+        {
+            use signal_hook::consts::{SIGINT, SIGTERM};
+            use signal_hook::iterator::Signals;
+
+            let mut signals = Signals::new([SIGINT, SIGTERM])?;
+            std::thread::Builder::new()
+                .name("appmap-termination".to_string())
+                .spawn(move || {
+                    if let Some(signal) = signals.forever().next() {
+                        layer.finish_and_exit(128 + signal);
+                    }
+                })?;
+        }
+        #[cfg(not(unix))]
+        ctrlc::set_handler(move || layer.finish_and_exit(CTRL_C_EXIT_CODE))?;
+        Ok(())
+    }
+
+    fn finish_and_exit(&self, code: i32) -> ! {
+        if let Err(e) = self.finish() {
+            warn!("could not write the AppMap on termination: {}", e);
+        }
+        std::process::exit(code)
+    }
+
+    /// Only tries to lock the recording, as the panic may have happened while this thread
+    /// held one of its locks.
+    fn save_on_panic(&self, info: &PanicHookInfo<'_>) -> Result<(), Box<dyn Error>> {
+        let (Ok(mut app_map), Ok(mut recordings), Ok(mut writer)) = (
+            self.test.try_lock(),
+            self.recordings.try_lock(),
+            self.writer.try_lock(),
+        ) else {
+            return Ok(());
+        };
+        static NEXT_OBJECT_ID: AtomicU64 = AtomicU64::new(1);
+        let mut message = panic_message(info.payload());
+        self.redactor.redact_value(&mut message);
+        let exception = ExceptionObject {
+            class: "panic".to_string(),
+            message: message.clone(),
+            object_id: ObjectId::from(NEXT_OBJECT_ID.fetch_add(1, Ordering::Relaxed)),
+            path: info.location().map(|x| PathBuf::from(x.file())),
+            lineno: info.location().map(|x| x.line() as usize),
+        };
+        record_panic_returns(&mut app_map, &mut recordings, &exception);
+        drop(recordings);
+        // also written by a streaming layer once it is finished
+        app_map
+            .data
+            .metadata
+            .get_or_insert_with(Default::default)
+            .exception = Some(MetadataExceptionObject {
+            class: "panic".to_string(),
+            message: Some(message),
+        });
+
+        match writer.as_mut() {
+            Some(writer) => {
+                for event in app_map.take_events() {
+                    writer.write_event(&event)?;
+                }
+                writer.flush()
+            }
+            None => app_map.write_to_file(&self.output),
+        }
+    }
+}
+
+/// Returns every recorded span of the current thread with `exception`.
+fn record_panic_returns(
+    app_map: &mut AppMap,
+    recordings: &mut Recordings,
+    exception: &ExceptionObject,
+) {
+    tracing::dispatcher::get_default(|dispatch| {
+        let Some(registry) = dispatch.downcast_ref::<Registry>() else {
+            return;
+        };
+        let Some(id) = dispatch.current_span().id().cloned() else {
+            return;
+        };
+        let Some(span) = registry.span(&id) else {
+            return;
+        };
+        for span in span.scope() {
+            // removed so the span does not return a second time when it is closed
+            let Some(call) = span.extensions_mut().remove::<RecordedCall>() else {
+                continue;
+            };
+            let elapsed = Some(call.start.elapsed().as_secs_f64());
+            let data = ReturnObjectType::Exception(ExceptionReturnObject {
+                exceptions: vec![exception.clone()],
+            });
+            app_map.add_function_return_event(call.thread_id, call.event_id, elapsed, data.clone());
+            add_scoped_returns(recordings, &call, elapsed, &data);
+        }
+    });
+}
+
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "panic".to_string()
+    }
+}
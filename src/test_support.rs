@@ -0,0 +1,180 @@
+//! Runtime support for the `#[appmap_test]` and `#[appmap_tokio_test]` attributes.
+
+use std::any::Any;
+use std::error::Error;
+use std::future::Future;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tracing::warn;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::appmap_definition::*;
+use crate::config::AppMapConfig;
+use crate::termination::panic_message;
+use crate::AppMapLayer;
+
+/// Implemented for the return types a test function may have.
+pub trait TestOutcome {
+    fn is_success(&self) -> bool;
+}
+impl TestOutcome for () {
+    fn is_success(&self) -> bool {
+        true
+    }
+}
+impl<T, E> TestOutcome for Result<T, E> {
+    fn is_success(&self) -> bool {
+        self.is_ok()
+    }
+}
+
+/// Runs the body of a recorded test. `expected_panic` is the message a `#[should_panic]` test
+/// has to panic with, empty if any panic is expected.
+pub fn run_test<R: TestOutcome>(
+    module_path: &str,
+    test_name: &str,
+    expected_panic: Option<&str>,
+    body: impl FnOnce() -> R,
+) -> R {
+    let Some((layer, directory)) = test_recorder(module_path, test_name) else {
+        return body();
+    };
+    let subscriber = Registry::default().with(layer.clone());
+    let result =
+        tracing::subscriber::with_default(subscriber, || catch_unwind(AssertUnwindSafe(body)));
+    finish_test(
+        &layer,
+        &directory,
+        module_path,
+        test_name,
+        expected_panic,
+        result,
+    )
+}
+
+/// Like [run_test], for the body of an async test.
+pub async fn run_async_test<R: TestOutcome>(
+    module_path: &str,
+    test_name: &str,
+    expected_panic: Option<&str>,
+    body: impl Future<Output = R>,
+) -> R {
+    let Some((layer, directory)) = test_recorder(module_path, test_name) else {
+        return body.await;
+    };
+    let subscriber = Registry::default().with(layer.clone());
+    let guard = tracing::subscriber::set_default(subscriber);
+    let result = CatchUnwind(Box::pin(body)).await;
+    drop(guard);
+    finish_test(
+        &layer,
+        &directory,
+        module_path,
+        test_name,
+        expected_panic,
+        result,
+    )
+}
+
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.0.as_mut();
+        match catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(x)) => Poll::Ready(Ok(x)),
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    }
+}
+
+/// A layer filtering and labeling spans as configured in `appmap.yml`, and the directory the
+/// map of the test is written to. If `appmap.yml` is invalid a warning is logged and the test
+/// runs without being recorded.
+fn test_recorder(module_path: &str, test_name: &str) -> Option<(AppMapLayer, PathBuf)> {
+    let recorder = AppMapConfig::find().and_then(|config| {
+        let layer = AppMapLayer::new().with_config(&config)?;
+        Ok((layer, config.appmap_dir().join("tests")))
+    });
+    match recorder {
+        Ok(recorder) => Some(recorder),
+        Err(e) => {
+            warn!(
+                "{}::{} is not recorded, appmap.yml is invalid: {}",
+                module_path, test_name, e
+            );
+            None
+        }
+    }
+}
+
+fn finish_test<R: TestOutcome>(
+    layer: &AppMapLayer,
+    directory: &Path,
+    module_path: &str,
+    test_name: &str,
+    expected_panic: Option<&str>,
+    result: Result<R, Box<dyn Any + Send>>,
+) -> R {
+    let name = format!("{}::{}", module_path, test_name);
+    let (test_status, test_failure) = match (&result, expected_panic) {
+        (Ok(_), Some(_)) => (
+            TestStatus::Failed,
+            Some("the test did not panic".to_string()),
+        ),
+        (Ok(x), None) if x.is_success() => (TestStatus::Succeeded, None),
+        (Ok(_), None) => (
+            TestStatus::Failed,
+            Some("the test returned an error".to_string()),
+        ),
+        (Err(panic), expected_panic) => {
+            let message = panic_message(panic.as_ref());
+            match expected_panic {
+                Some(expected) if message.contains(expected) => (TestStatus::Succeeded, None),
+                _ => (TestStatus::Failed, Some(message)),
+            }
+        }
+    };
+    let mut data = layer.test.lock().unwrap().data.clone();
+    data.metadata = Some(MetadataObject {
+        name: Some(name.clone()),
+        language: Some(LanguageObject {
+            name: "rust".to_string(),
+            ..Default::default()
+        }),
+        recorder: Some(RecorderObject {
+            name: "appmap_tracing_test".to_string(),
+            type_: Some("tests".to_string()),
+        }),
+        recording: Some(RecordingObject {
+            defined_class: module_path.to_string(),
+            method_id: test_name.to_string(),
+        }),
+        test_status: Some(test_status),
+        test_failure: test_failure.map(|message| TestFailureObject {
+            message,
+            location: None,
+        }),
+        ..Default::default()
+    });
+    if let Err(e) = write_test_map(&data, directory, &name) {
+        warn!("could not write the AppMap of {}: {}", name, e);
+    }
+    match result {
+        Ok(x) => x,
+        Err(panic) => resume_unwind(panic),
+    }
+}
+
+fn write_test_map(data: &AppMapObject, directory: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(directory)?;
+    let file_name = format!("{}.appmap.json", name.replace("::", "_"));
+    data.write_to_file(directory.join(file_name))
+}
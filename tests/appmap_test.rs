@@ -0,0 +1,143 @@
+//! The recorded tests are ignored by the harness and run by the tests checking their maps, so
+//! each map is only written once.
+
+use std::panic::catch_unwind;
+use std::path::PathBuf;
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::config::AppMapConfig;
+use appmap_tracing_test::{appmap_test, appmap_tokio_test};
+use tracing::{info_span, Instrument};
+
+#[appmap_test]
+#[ignore]
+fn recorded_passing() {
+    info_span!(target: "my_app::orders", "create").in_scope(|| {});
+}
+
+#[appmap_test]
+#[ignore]
+fn recorded_failing() {
+    info_span!(target: "my_app::orders", "create").in_scope(|| panic!("order 7 was not created"));
+}
+
+#[appmap_test]
+#[ignore]
+#[should_panic(expected = "was not created")]
+fn recorded_panicking_as_expected() {
+    panic!("order 7 was not created");
+}
+
+#[appmap_test]
+#[ignore]
+#[should_panic]
+fn recorded_not_panicking() {}
+
+#[appmap_test]
+#[ignore]
+fn recorded_returning_error() -> Result<(), String> {
+    Err("order 7 was not created".to_string())
+}
+
+#[appmap_tokio_test]
+#[ignore]
+async fn recorded_async() {
+    let span = info_span!(target: "my_app::orders", "create");
+    async {
+        tokio::task::yield_now().await;
+    }
+    .instrument(span)
+    .await;
+}
+
+fn test_map(test_name: &str) -> AppMapObject {
+    let path: PathBuf = AppMapConfig::find()
+        .unwrap()
+        .appmap_dir()
+        .join("tests")
+        .join(format!("appmap_test_{}.appmap.json", test_name));
+    AppMapObject::read_from_file(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+fn calls(data: &AppMapObject) -> Vec<String> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => {
+                Some(format!("{}::{}", call.defined_class, call.method_id))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn passing_tests_are_recorded_as_succeeded() {
+    recorded_passing();
+    let data = test_map("recorded_passing");
+    let metadata = data.metadata.as_ref().unwrap();
+    assert_eq!(
+        metadata.name.as_deref(),
+        Some("appmap_test::recorded_passing")
+    );
+    assert_eq!(metadata.test_status, Some(TestStatus::Succeeded));
+    assert_eq!(metadata.test_failure, None);
+    let recording = metadata.recording.as_ref().unwrap();
+    assert_eq!(recording.defined_class, "appmap_test");
+    assert_eq!(recording.method_id, "recorded_passing");
+    assert_eq!(calls(&data), ["my_app::orders::create"]);
+}
+
+#[test]
+fn panicking_tests_are_recorded_as_failed() {
+    assert!(catch_unwind(recorded_failing).is_err());
+    let data = test_map("recorded_failing");
+    let metadata = data.metadata.as_ref().unwrap();
+    assert_eq!(metadata.test_status, Some(TestStatus::Failed));
+    assert_eq!(
+        metadata.test_failure.as_ref().unwrap().message,
+        "order 7 was not created"
+    );
+    // the call the panic unwound through is still closed
+    assert_eq!(calls(&data), ["my_app::orders::create"]);
+    assert_eq!(data.events.len(), 2);
+}
+
+#[test]
+fn tests_panicking_as_expected_are_recorded_as_succeeded() {
+    assert!(catch_unwind(recorded_panicking_as_expected).is_err());
+    let metadata = test_map("recorded_panicking_as_expected").metadata.unwrap();
+    assert_eq!(metadata.test_status, Some(TestStatus::Succeeded));
+    assert_eq!(metadata.test_failure, None);
+}
+
+#[test]
+fn tests_expected_to_panic_are_recorded_as_failed_if_they_do_not() {
+    recorded_not_panicking();
+    let metadata = test_map("recorded_not_panicking").metadata.unwrap();
+    assert_eq!(metadata.test_status, Some(TestStatus::Failed));
+    assert_eq!(
+        metadata.test_failure.unwrap().message,
+        "the test did not panic"
+    );
+}
+
+#[test]
+fn tests_returning_an_error_are_recorded_as_failed() {
+    assert!(recorded_returning_error().is_err());
+    let metadata = test_map("recorded_returning_error").metadata.unwrap();
+    assert_eq!(metadata.test_status, Some(TestStatus::Failed));
+}
+
+#[test]
+fn async_tests_are_recorded() {
+    recorded_async();
+    let data = test_map("recorded_async");
+    let metadata = data.metadata.as_ref().unwrap();
+    assert_eq!(
+        metadata.name.as_deref(),
+        Some("appmap_test::recorded_async")
+    );
+    assert_eq!(metadata.test_status, Some(TestStatus::Succeeded));
+    assert_eq!(calls(&data), ["my_app::orders::create"]);
+}
@@ -0,0 +1,292 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::convert::chrome_trace::{
+    from_chrome_trace, read_chrome_trace, to_chrome_trace, ChromeTrace, ChromeTraceOptions,
+    ChromeTracePhase,
+};
+use serde_json::{json, Value};
+
+/// `create(id: 7)` returns "ok" after 1s and calls `validate`, which takes 0.25s. The events
+/// have no timestamps, so the calls are laid out by their elapsed time.
+fn sample() -> AppMapObject {
+    serde_json::from_value(json!({
+        "version": "1.12",
+        "classMap": [],
+        "events": [
+            {
+                "id": 1, "thread_id": 1, "event": "call",
+                "defined_class": "my_app::orders", "method_id": "create", "static": true,
+                "parameters": [{"name": "id", "class": "u64", "value": "7"}],
+                "type": "function"
+            },
+            {
+                "id": 2, "thread_id": 1, "event": "call",
+                "defined_class": "my_app::orders", "method_id": "validate", "static": true,
+                "type": "function"
+            },
+            {"id": 3, "thread_id": 1, "event": "return", "parent_id": 2, "elapsed": 0.25},
+            {
+                "id": 4, "thread_id": 1, "event": "return", "parent_id": 1, "elapsed": 1.0,
+                "return_value": {"class": "&str", "value": "ok"}
+            }
+        ]
+    }))
+    .unwrap()
+}
+
+#[test]
+fn calls_are_complete_events_with_args() {
+    let trace = to_chrome_trace(&sample(), &ChromeTraceOptions::default());
+    let events: Vec<(&str, &str, f64, Option<f64>)> = trace
+        .trace_events
+        .iter()
+        .map(|x| (x.name.as_str(), x.ph.as_str(), x.ts, x.dur))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            ("create", "X", 0.0, Some(1_000_000.0)),
+            ("validate", "X", 0.0, Some(250_000.0)),
+        ]
+    );
+
+    let create = &trace.trace_events[0];
+    assert_eq!(create.cat.as_deref(), Some("my_app::orders"));
+    assert_eq!(create.tid, 1);
+    let args = create.args.as_ref().unwrap();
+    assert_eq!(args["event_id"], Value::from(1));
+    assert_eq!(args["type"], Value::from("function"));
+    assert_eq!(args["id"], Value::from("u64: 7"));
+    assert_eq!(args["return_value"], Value::from("&str: ok"));
+}
+
+#[test]
+fn calls_can_be_begin_and_end_events() {
+    let options = ChromeTraceOptions {
+        phase: ChromeTracePhase::BeginEnd,
+        pid: 42,
+    };
+    let trace = to_chrome_trace(&sample(), &options);
+    let events: Vec<(&str, &str, f64)> = trace
+        .trace_events
+        .iter()
+        .map(|x| (x.name.as_str(), x.ph.as_str(), x.ts))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            ("create", "B", 0.0),
+            ("validate", "B", 0.0),
+            ("validate", "E", 250_000.0),
+            ("create", "E", 1_000_000.0),
+        ]
+    );
+    assert!(trace.trace_events.iter().all(|x| x.pid == 42));
+    assert!(trace.trace_events.iter().all(|x| x.dur.is_none()));
+
+    // the return value is only on the end event, and an end event without one has no args
+    let begin = trace.trace_events[0].args.as_ref().unwrap();
+    assert!(!begin.contains_key("return_value"));
+    assert_eq!(trace.trace_events[2].args, None);
+    let end = trace.trace_events[3].args.as_ref().unwrap();
+    assert_eq!(end["return_value"], Value::from("&str: ok"));
+}
+
+fn trace(events: Value) -> ChromeTrace {
+    serde_json::from_value(json!({ "traceEvents": events })).unwrap()
+}
+
+/// Every event as "call <thread> <method>" or "return <thread> <method> <elapsed>", in order.
+fn summary(data: &AppMapObject) -> Vec<String> {
+    let mut methods = std::collections::HashMap::new();
+    data.events
+        .iter()
+        .map(|event| match &event.event {
+            EventObjectType::Call(call) => {
+                methods.insert(event.id, call.method_id.clone());
+                format!("call {} {}", event.thread_id, call.method_id)
+            }
+            EventObjectType::Return(ret) => format!(
+                "return {} {} {:?}",
+                event.thread_id, methods[&ret.parent_id], ret.elapsed
+            ),
+        })
+        .collect()
+}
+
+#[test]
+fn begin_and_end_events_are_paired_per_thread() {
+    let trace = trace(json!([
+        {"name": "my_app::orders::create", "ph": "B", "ts": 0.0, "pid": 1, "tid": 1},
+        {"name": "my_app::orders::notify", "ph": "B", "ts": 100.0, "pid": 1, "tid": 2},
+        {"name": "my_app::orders::validate", "ph": "B", "ts": 200.0, "pid": 1, "tid": 1},
+        {"ph": "E", "ts": 300.0, "pid": 1, "tid": 1, "args": {"return_value": true}},
+        {"ph": "E", "ts": 400.0, "pid": 1, "tid": 2},
+        {"ph": "E", "ts": 1000.0, "pid": 1, "tid": 1},
+    ]));
+    let data = from_chrome_trace(&trace);
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 create",
+            "call 2 notify",
+            "call 1 validate",
+            "return 1 validate Some(0.0001)",
+            "return 2 notify Some(0.0003)",
+            "return 1 create Some(0.001)",
+        ]
+    );
+    let EventObjectType::Return(ret) = &data.events[3].event else {
+        panic!("not a return");
+    };
+    let ReturnObjectType::Function(function) = &ret.data else {
+        panic!("no return value");
+    };
+    let return_value = function.return_value.as_ref().unwrap();
+    assert_eq!(
+        (return_value.class.as_str(), return_value.value.as_str()),
+        ("bool", "true")
+    );
+    assert_eq!(data.events[0].timestamp, Some(0.0));
+    assert_eq!(data.events[5].timestamp, Some(0.001));
+}
+
+#[test]
+fn complete_events_are_nested_by_their_time() {
+    let trace = trace(json!([
+        {"name": "create", "cat": "my_app::orders", "ph": "X", "ts": 0.0, "dur": 1000.0, "tid": 1, "args": {"id": 7}},
+        {"name": "validate", "cat": "my_app::orders", "ph": "X", "ts": 100.0, "dur": 200.0, "tid": 1},
+        {"name": "save", "cat": "my_app::orders", "ph": "X", "ts": 300.0, "dur": 100.0, "tid": 1},
+        {"name": "counter", "ph": "C", "ts": 50.0, "tid": 1},
+    ]));
+    let data = from_chrome_trace(&trace);
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 create",
+            "call 1 validate",
+            "return 1 validate Some(0.0002)",
+            "call 1 save",
+            "return 1 save Some(0.0001)",
+            "return 1 create Some(0.001)",
+        ]
+    );
+    let EventObjectType::Call(create) = &data.events[0].event else {
+        panic!("not a call");
+    };
+    assert_eq!(create.defined_class, "my_app::orders");
+    let parameter = &create.parameters.as_ref().unwrap()[0];
+    assert_eq!(
+        (
+            parameter.name.as_deref(),
+            parameter.class.as_str(),
+            parameter.value.as_str()
+        ),
+        (Some("id"), "u64", "7")
+    );
+}
+
+#[test]
+fn calls_ending_after_their_parent_return_with_it() {
+    let trace = trace(json!([
+        {"name": "app::handle", "ph": "X", "ts": 0.0, "dur": 100.0, "tid": 1},
+        {"name": "app::flush", "ph": "X", "ts": 50.0, "dur": 100.0, "tid": 1},
+        {"name": "app::next", "ph": "X", "ts": 120.0, "dur": 10.0, "tid": 1},
+    ]));
+    let data = from_chrome_trace(&trace);
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 handle",
+            "call 1 flush",
+            "return 1 flush Some(0.0001)",
+            "return 1 handle Some(0.0001)",
+            "call 1 next",
+            "return 1 next Some(1e-5)",
+        ]
+    );
+    // `flush` returns at the end of `handle`
+    assert_eq!(data.events[2].timestamp, Some(0.0001));
+}
+
+#[test]
+fn threads_are_told_apart_by_process_and_thread_id() {
+    let trace = trace(json!([
+        {"name": "app::a", "ph": "X", "ts": 0.0, "dur": 10.0, "pid": 2, "tid": 7},
+        {"name": "app::b", "ph": "X", "ts": 0.0, "dur": 10.0, "pid": 1, "tid": 7},
+        {"name": "app::c", "ph": "X", "ts": 0.0, "dur": 10.0, "pid": 1, "tid": 4_294_967_303_u64},
+        {"name": "app::d", "ph": "X", "ts": 20.0, "dur": 10.0, "pid": 2, "tid": 7},
+    ]));
+    let data = from_chrome_trace(&trace);
+    let threads: Vec<(String, u32)> = data
+        .events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some((call.method_id.clone(), x.thread_id)),
+            _ => None,
+        })
+        .collect();
+    let expected = [("a", 1), ("b", 2), ("c", 3), ("d", 1)];
+    assert_eq!(threads.len(), expected.len());
+    for (method, thread_id) in expected {
+        assert!(
+            threads.contains(&(method.to_string(), thread_id)),
+            "{:?}",
+            threads
+        );
+    }
+}
+
+#[test]
+fn unterminated_arrays_are_read() {
+    let path = std::env::temp_dir().join(format!("unterminated-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        concat!(
+            "[\n",
+            r#"{"name":"app::serve","ph":"B","ts":0.0,"pid":1,"tid":1},"#,
+            "\n",
+            r#"{"name":"app::handle","ph":"X","ts":10.0,"dur":5.0,"pid":1,"tid":1},"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    let data = read_chrome_trace(&path).unwrap();
+    // `serve` never ended, so it has no elapsed time and returns after `handle`
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 serve",
+            "call 1 handle",
+            "return 1 handle Some(5e-6)",
+            "return 1 serve None",
+        ]
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn exported_traces_are_imported_as_they_were_recorded() {
+    // the sample has no timestamps, the imported events take theirs from the trace
+    let without_timestamps = |data: AppMapObject| -> Vec<EventObject> {
+        data.events
+            .into_iter()
+            .map(|x| EventObject {
+                timestamp: None,
+                ..x
+            })
+            .collect()
+    };
+    for phase in [ChromeTracePhase::Complete, ChromeTracePhase::BeginEnd] {
+        let options = ChromeTraceOptions {
+            phase,
+            ..Default::default()
+        };
+        let imported = from_chrome_trace(&to_chrome_trace(&sample(), &options));
+        assert_eq!(
+            serde_json::to_value(without_timestamps(imported)).unwrap(),
+            serde_json::to_value(without_timestamps(sample())).unwrap(),
+            "{:?}",
+            phase
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use appmap_tracing_test::appmap_definition::ReturnObjectType;
+use appmap_tracing_test::AppMap;
+
+/// Records `method` of "my_app::orders" on `thread_id`, calling `children` inside it.
+pub fn call(
+    app_map: &mut AppMap,
+    thread_id: u32,
+    method: &str,
+    elapsed: f64,
+    children: impl FnOnce(&mut AppMap),
+) {
+    let call_id = app_map.add_function_call_event(
+        thread_id,
+        "my_app::orders".to_string(),
+        method.to_string(),
+        None,
+        None,
+        true,
+        None,
+    );
+    children(app_map);
+    app_map.add_function_return_event(thread_id, call_id, Some(elapsed), ReturnObjectType::Normal);
+}
@@ -0,0 +1,105 @@
+mod common;
+
+use appmap_tracing_test::appmap_definition::diff::DiffOptions;
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMap;
+
+use common::call;
+
+/// Records `method` of "my_app::orders" with the type `type_`, calling `children` inside it.
+fn typed_call(
+    app_map: &mut AppMap,
+    method: &str,
+    elapsed: f64,
+    type_: CallObjectType,
+    children: impl FnOnce(&mut AppMap),
+) {
+    call(app_map, 1, method, elapsed, |app_map| {
+        if let Some(EventObjectType::Call(call)) =
+            app_map.data.events.last_mut().map(|x| &mut x.event)
+        {
+            call.type_ = type_;
+        }
+        children(app_map);
+    });
+}
+
+fn query() -> CallObjectType {
+    CallObjectType::SqlQuery(SqlQueryCallObject {
+        sql_query: SqlQueryObject {
+            database_type: "postgresql".to_string(),
+            sql: "SELECT * FROM orders".to_string(),
+            explain_sql: None,
+            server_version: None,
+        },
+    })
+}
+
+/// `create` calls `validate`, which takes `validate_elapsed`, and runs `queries` queries.
+fn recording(validate_elapsed: f64, queries: usize, notify: bool) -> AppMapObject {
+    let mut app_map = AppMap::new();
+    typed_call(
+        &mut app_map,
+        "create",
+        1.0,
+        CallObjectType::Function,
+        |app_map| {
+            typed_call(
+                app_map,
+                "validate",
+                validate_elapsed,
+                CallObjectType::Function,
+                |_| {},
+            );
+            for _ in 0..queries {
+                typed_call(app_map, "query", 0.01, query(), |_| {});
+            }
+            if notify {
+                typed_call(app_map, "notify", 0.1, CallObjectType::Function, |_| {});
+            }
+        },
+    );
+    app_map.data
+}
+
+#[test]
+fn identical_maps_have_no_changes() {
+    let base = recording(0.1, 1, false);
+    let diff = base.diff(&base.clone(), &DiffOptions::default());
+    assert!(diff.is_empty());
+    assert_eq!(diff.to_string(), "no changes\n");
+}
+
+#[test]
+fn functions_calls_events_and_regressions_are_reported() {
+    let base = recording(0.1, 1, false);
+    let head = recording(0.5, 2, true);
+    let diff = base.diff(&head, &DiffOptions::default());
+
+    assert_eq!(
+        diff.to_string(),
+        "+ function my_app::orders::notify\n\
+         + call my_app::orders::create -> my_app::orders::notify\n\
+         ~ sql_query SELECT * FROM orders (1 -> 2)\n\
+         ! slower my_app::orders::validate (0.100000s -> 0.500000s)\n"
+    );
+    assert!(diff.removed_functions.is_empty());
+    assert_eq!(diff.changed_events[0].base_count, 1);
+    assert_eq!(diff.changed_events[0].head_count, 2);
+
+    let reverse = head.diff(&base, &DiffOptions::default());
+    assert_eq!(reverse.removed_functions.len(), 1);
+    assert_eq!(reverse.removed_calls.len(), 1);
+    assert!(reverse.elapsed_regressions.is_empty());
+}
+
+#[test]
+fn slowdowns_below_the_threshold_are_not_reported() {
+    let base = recording(0.1, 1, false);
+    let head = recording(0.11, 1, false);
+    assert!(base.diff(&head, &DiffOptions::default()).is_empty());
+    let strict = DiffOptions {
+        elapsed_threshold: 0.05,
+    };
+    assert_eq!(base.diff(&head, &strict).elapsed_regressions.len(), 1);
+}
@@ -0,0 +1,70 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::config::AppMapConfig;
+use appmap_tracing_test::filter::SpanFilter;
+use appmap_tracing_test::AppMapLayer;
+use tracing::{debug_span, info_span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn recorded_calls(layer: &AppMapLayer) -> Vec<String> {
+    layer
+        .test
+        .lock()
+        .unwrap()
+        .data
+        .events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => {
+                Some(format!("{}::{}", call.defined_class, call.method_id))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn spans_are_filtered_as_configured() {
+    let config = AppMapConfig::load("tests/fixtures/filter.appmap.yml").unwrap();
+    let filter = SpanFilter::from_config(&config).unwrap();
+    let layer = AppMapLayer::new().with_filter(filter);
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!(target: "my_app::orders", "create").in_scope(|| {
+            info_span!(target: "tokio::runtime", "poll").in_scope(|| {});
+            info_span!(target: "my_app::orders", "validate").in_scope(|| {});
+            debug_span!(target: "my_app::orders", "details").in_scope(|| {});
+            info_span!(target: "my_app::Cache", "get").in_scope(|| {});
+            info_span!(target: "my_app::Cache", "insert").in_scope(|| {});
+        });
+        info_span!(target: "my_app::health", "check").in_scope(|| {});
+        info_span!(target: "my_application", "run").in_scope(|| {});
+    });
+    assert_eq!(
+        recorded_calls(&layer),
+        [
+            "my_app::orders::create",
+            "my_app::orders::validate",
+            "my_app::Cache::insert"
+        ]
+    );
+}
+
+#[test]
+fn everything_is_recorded_without_configuration() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        debug_span!(target: "tokio::runtime", "poll").in_scope(|| {});
+    });
+    assert_eq!(recorded_calls(&layer), ["tokio::runtime::poll"]);
+}
+
+#[test]
+fn invalid_levels_are_rejected() {
+    let config = AppMapConfig {
+        level: Some("loud".to_string()),
+        ..Default::default()
+    };
+    assert!(SpanFilter::from_config(&config).is_err());
+}
@@ -0,0 +1,221 @@
+use appmap_tracing_test::appmap_definition::findings::*;
+use appmap_tracing_test::appmap_definition::*;
+use serde_json::{json, Value};
+
+/// Builds the events of nested calls from `(depth, method, type fields)`, returning every call
+/// right before the next call at the same or a lower depth.
+fn sample(calls: &[(usize, &str, Value)]) -> AppMapObject {
+    let mut events = vec![];
+    let mut open: Vec<u64> = vec![];
+    let mut id = 0;
+    let close = |events: &mut Vec<Value>, id: &mut u64, parent_id: u64| {
+        *id += 1;
+        events.push(json!({"id": *id, "thread_id": 1, "event": "return", "parent_id": parent_id}));
+    };
+    for (depth, method, fields) in calls {
+        while open.len() > *depth {
+            let parent_id = open.pop().unwrap();
+            close(&mut events, &mut id, parent_id);
+        }
+        id += 1;
+        let mut call = json!({
+            "id": id, "thread_id": 1, "event": "call",
+            "defined_class": "my_app", "method_id": method, "static": true,
+            "path": "src/lib.rs", "lineno": id,
+        });
+        call.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        events.push(call);
+        open.push(id);
+    }
+    while let Some(parent_id) = open.pop() {
+        close(&mut events, &mut id, parent_id);
+    }
+    serde_json::from_value(json!({
+        "version": "1.12",
+        "metadata": {"name": "orders"},
+        "classMap": [],
+        "events": events,
+    }))
+    .unwrap()
+}
+
+fn function() -> Value {
+    json!({"type": "function"})
+}
+
+fn query(sql: &str) -> Value {
+    json!({"type": "sqlQuery", "sql_query": {"database_type": "postgresql", "sql": sql}})
+}
+
+fn request(url: &str) -> Value {
+    json!({"type": "httpClientRequest", "http_client_request": {"request_method": "GET", "url": url}})
+}
+
+fn options() -> FindingsOptions {
+    FindingsOptions {
+        min_query_repeats: 2,
+        min_request_repeats: 2,
+        max_function_calls: 2,
+    }
+}
+
+fn ids(events: &[EventObject]) -> Vec<u64> {
+    events.iter().map(|x| *x.id).collect()
+}
+
+#[test]
+fn queries_are_grouped_by_the_call_they_ran_in() {
+    let data = sample(&[
+        (0, "list", function()),
+        (1, "load", function()),
+        (2, "query", query("SELECT * FROM items WHERE id = 1")),
+        (2, "query", query("SELECT *  FROM items WHERE id = 2")),
+        // below the same root, but in another call
+        (1, "total", function()),
+        (2, "query", query("SELECT * FROM items WHERE id = 3")),
+    ]);
+    let findings = data.findings(&options());
+    assert_eq!(findings.len(), 1);
+    let finding = &findings[0];
+    assert_eq!(finding.rule_id, N_PLUS_ONE_QUERY);
+    assert_eq!(
+        finding.message,
+        "SELECT * FROM items WHERE id = ? ran 2 times in my_app::load"
+    );
+    assert_eq!(finding.occurrence_count, 2);
+    assert_eq!(*finding.scope.id, 2);
+    assert_eq!(*finding.event.id, 3);
+    assert_eq!(ids(&finding.related_events), [3, 5]);
+    assert_eq!(
+        finding.stack,
+        ["src/lib.rs:3", "src/lib.rs:2", "src/lib.rs:1"]
+    );
+}
+
+#[test]
+fn root_queries_are_scoped_to_the_first_of_them() {
+    let data = sample(&[
+        (0, "query", query("SELECT 1")),
+        (0, "query", query("SELECT 2")),
+    ]);
+    let findings = data.findings(&options());
+    assert_eq!(findings.len(), 1);
+    assert_eq!(*findings[0].scope.id, 1);
+    assert_eq!(ids(&findings[0].related_events), [1, 3]);
+}
+
+#[test]
+fn repeated_requests_are_reported_per_url() {
+    let data = sample(&[
+        (0, "sync", function()),
+        (1, "get", request("https://example.com/items")),
+        (1, "get", request("https://example.com/items")),
+        (1, "get", request("https://example.com/users")),
+    ]);
+    let findings = data.findings(&options());
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].rule_id, REPEATED_HTTP_CLIENT_REQUEST);
+    assert_eq!(
+        findings[0].message,
+        "GET https://example.com/items was sent 2 times in my_app::sync"
+    );
+    assert_eq!(*findings[0].scope.id, 1);
+}
+
+#[test]
+fn repeated_function_calls_of_several_calls_are_reported_once() {
+    let data = sample(&[
+        (0, "list", function()),
+        (1, "render", function()),
+        (2, "format", function()),
+        (2, "format", function()),
+        (2, "format", function()),
+        (1, "render", function()),
+        (2, "format", function()),
+        (2, "format", function()),
+        (2, "format", function()),
+        (2, "format", function()),
+    ]);
+    let findings = data.findings(&options());
+    assert_eq!(findings.len(), 1);
+    let finding = &findings[0];
+    assert_eq!(finding.rule_id, REPEATED_FUNCTION_CALL);
+    assert_eq!(
+        finding.message,
+        "my_app::format was called 7 times by 2 calls of my_app::render"
+    );
+    assert_eq!(finding.occurrence_count, 7);
+    assert_eq!(finding.related_events.len(), 7);
+    assert_eq!(*finding.scope.id, 2);
+    let options = FindingsOptions {
+        max_function_calls: 3,
+        ..options()
+    };
+    assert_eq!(data.findings(&options)[0].occurrence_count, 4);
+}
+
+#[test]
+fn findings_of_different_scopes_have_different_hashes() {
+    let data = sample(&[
+        (0, "list", function()),
+        (1, "query", query("SELECT 1")),
+        (1, "query", query("SELECT 1")),
+        (0, "show", function()),
+        (1, "query", query("SELECT 1")),
+        (1, "query", query("SELECT 1")),
+    ]);
+    let findings = data.findings(&options());
+    assert_eq!(findings.len(), 2);
+    assert_ne!(findings[0].hash_v2, findings[1].hash_v2);
+    assert_eq!(findings[0].hash_v2.len(), 16);
+    // the hash does not depend on the map
+    let other = sample(&[
+        (0, "show", function()),
+        (1, "query", query("SELECT 2")),
+        (1, "query", query("SELECT 3")),
+    ]);
+    assert_eq!(other.findings(&options())[0].hash_v2, findings[1].hash_v2);
+}
+
+#[test]
+fn reports_have_the_json_shape_of_the_appmap_scanner() {
+    let data = sample(&[
+        (0, "list", function()),
+        (1, "query", query("SELECT 1")),
+        (1, "query", query("SELECT 2")),
+    ]);
+    let mut report = FindingsReport::new();
+    report.add("tmp/orders.appmap.json", &data, &options());
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(
+        json["configuration"]["checks"],
+        json!([
+            {"rule": N_PLUS_ONE_QUERY},
+            {"rule": REPEATED_HTTP_CLIENT_REQUEST},
+            {"rule": REPEATED_FUNCTION_CALL},
+        ])
+    );
+    assert_eq!(
+        json["appMapMetadata"]["tmp/orders.appmap.json"]["name"],
+        "orders"
+    );
+    assert_eq!(json["checks"][0]["impactDomain"], "Performance");
+    assert_eq!(json["checks"][0]["rule"]["title"], "N plus 1 SQL query");
+    let finding = &json["findings"][0];
+    assert_eq!(finding["appMapFile"], "tmp/orders.appmap.json");
+    assert_eq!(finding["checkId"], N_PLUS_ONE_QUERY);
+    assert_eq!(finding["ruleTitle"], "N plus 1 SQL query");
+    assert_eq!(finding["occurranceCount"], 2);
+    assert!(finding["hash_v2"].is_string());
+    assert_eq!(finding["event"]["id"], 2);
+    assert_eq!(finding["scope"]["id"], 1);
+    assert_eq!(finding["relatedEvents"].as_array().unwrap().len(), 2);
+    assert_eq!(finding["impactDomain"], "Performance");
+    assert!(finding.get("severity").is_none());
+    assert_eq!(
+        serde_json::from_value::<FindingsReport>(json).unwrap(),
+        report
+    );
+}
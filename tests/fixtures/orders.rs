@@ -0,0 +1,17 @@
+use tracing::instrument;
+
+/// Creates an order.
+///
+/// Fails if the order is invalid.
+#[instrument]
+pub fn create(id: u64) -> u64 {
+    id
+}
+
+struct Orders;
+
+impl Orders {
+    /// Cancels the order.
+    #[instrument(skip(self))]
+    fn cancel(&self) {}
+}
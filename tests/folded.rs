@@ -0,0 +1,69 @@
+mod common;
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::convert::folded::{to_folded, FoldedOptions, FoldedWeight};
+use appmap_tracing_test::AppMap;
+
+use common::call;
+
+/// `create` takes 1s on thread 1, of which `validate` takes 0.25s twice. `validate` also
+/// runs on thread 2 for 0.5s.
+fn sample() -> AppMapObject {
+    let mut app_map = AppMap::new();
+    call(&mut app_map, 1, "create", 1.0, |app_map| {
+        call(app_map, 1, "validate", 0.25, |_| {});
+        call(app_map, 1, "validate", 0.25, |_| {});
+    });
+    call(&mut app_map, 2, "validate", 0.5, |_| {});
+    app_map.data
+}
+
+#[test]
+fn stacks_are_weighted_by_self_time() {
+    assert_eq!(
+        to_folded(&sample(), &FoldedOptions::default()),
+        "my_app::orders::create 500000\n\
+         my_app::orders::create;my_app::orders::validate 500000\n\
+         my_app::orders::validate 500000\n"
+    );
+}
+
+#[test]
+fn stacks_can_be_weighted_by_calls() {
+    let options = FoldedOptions {
+        weight: FoldedWeight::Calls,
+        per_thread: false,
+    };
+    assert_eq!(
+        to_folded(&sample(), &options),
+        "my_app::orders::create 1\n\
+         my_app::orders::create;my_app::orders::validate 2\n\
+         my_app::orders::validate 1\n"
+    );
+}
+
+#[test]
+fn stacks_can_start_with_their_thread() {
+    let options = FoldedOptions {
+        weight: FoldedWeight::Calls,
+        per_thread: true,
+    };
+    assert_eq!(
+        to_folded(&sample(), &options),
+        "thread 1;my_app::orders::create 1\n\
+         thread 1;my_app::orders::create;my_app::orders::validate 2\n\
+         thread 2;my_app::orders::validate 1\n"
+    );
+}
+
+#[test]
+fn calls_without_self_time_are_left_out() {
+    let mut app_map = AppMap::new();
+    call(&mut app_map, 1, "create", 0.5, |app_map| {
+        call(app_map, 1, "validate", 0.5, |_| {});
+    });
+    assert_eq!(
+        to_folded(&app_map.data, &FoldedOptions::default()),
+        "my_app::orders::create;my_app::orders::validate 500000\n"
+    );
+}
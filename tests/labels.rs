@@ -0,0 +1,127 @@
+use appmap_tracing_test::appmap_definition::rules::{CallMatcher, RuleConfig, RuleSet};
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::config::AppMapConfig;
+use appmap_tracing_test::AppMapLayer;
+use tracing::field::Empty;
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// The labels of every function of the class map, as "class::method: labels".
+fn labels(data: &AppMapObject) -> Vec<String> {
+    fn collect(node: &CodeObjectType, path: &str, result: &mut Vec<String>) {
+        match node {
+            CodeObjectType::Package(x) => x
+                .children
+                .iter()
+                .flatten()
+                .for_each(|child| collect(child, &format!("{}{}::", path, x.name), result)),
+            CodeObjectType::Class(x) => x
+                .children
+                .iter()
+                .flatten()
+                .for_each(|child| collect(child, &format!("{}{}::", path, x.name), result)),
+            CodeObjectType::Function(x) => {
+                let labels = x.labels.clone().unwrap_or_default();
+                result.push(format!("{}{}: {}", path, x.name, labels.join(",")));
+            }
+        }
+    }
+    let mut result = vec![];
+    for node in data.class_map.iter() {
+        collect(node, "", &mut result);
+    }
+    result
+}
+
+fn labels_matcher(label: &str) -> CallMatcher {
+    CallMatcher {
+        labels: vec![label.to_string()],
+        ..Default::default()
+    }
+}
+
+/// Logs a token, which is only known after the `log` span was entered.
+fn record(layer: &AppMapLayer) {
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!(target: "my_app::serde", "from_bytes").in_scope(|| {});
+        info_span!(target: "my_app::logger", "log").in_scope(|| {
+            let span = info_span!(target: "my_app::auth", "token", appmap.labels = Empty);
+            span.in_scope(|| span.record("appmap.labels", "secret"));
+            info_span!(target: "my_app::auth", "login", appmap.labels = "security, auth")
+                .in_scope(|| {});
+        });
+    });
+}
+
+#[test]
+fn labels_are_taken_from_span_fields_and_the_config() {
+    let config = AppMapConfig::load("tests/fixtures/labels.appmap.yml").unwrap();
+    let layer = AppMapLayer::new().with_config(&config).unwrap();
+    record(&layer);
+    let data = layer.test.lock().unwrap().data.clone();
+    assert_eq!(
+        labels(&data),
+        [
+            "my_app::serde::from_bytes: deserialize.unsafe",
+            "my_app::logger::log: log",
+            "my_app::auth::token: secret",
+            "my_app::auth::login: security,auth",
+        ]
+    );
+}
+
+#[test]
+fn labels_recorded_after_the_span_was_entered_are_in_scoped_recordings() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    let data = tracing::subscriber::with_default(subscriber, || {
+        let recording = layer.start_recording("token");
+        let span = info_span!(target: "my_app::auth", "token", appmap.labels = Empty);
+        span.in_scope(|| span.record("appmap.labels", "secret"));
+        recording.stop()
+    });
+    assert_eq!(labels(&data), ["my_app::auth::token: secret"]);
+}
+
+#[test]
+fn scanner_rules_see_the_labels() {
+    let config = AppMapConfig::load("tests/fixtures/labels.appmap.yml").unwrap();
+    let layer = AppMapLayer::new().with_config(&config).unwrap();
+    record(&layer);
+    let data = layer.test.lock().unwrap().data.clone();
+    let rules = RuleSet {
+        rules: vec![
+            RuleConfig {
+                id: "secret-in-log".to_string(),
+                match_: labels_matcher("secret"),
+                ancestor: Some(labels_matcher("log")),
+                ..Default::default()
+            },
+            RuleConfig {
+                id: "deserialize-unsafe".to_string(),
+                match_: labels_matcher("deserialize.unsafe"),
+                ..Default::default()
+            },
+        ],
+    };
+    let findings: Vec<(String, String)> = data
+        .check_rules(&rules)
+        .into_iter()
+        .map(|x| (x.rule_id, x.message))
+        .collect();
+    assert_eq!(
+        findings,
+        [
+            (
+                "secret-in-log".to_string(),
+                "my_app::auth::token was called under my_app::logger::log".to_string()
+            ),
+            (
+                "deserialize-unsafe".to_string(),
+                "my_app::serde::from_bytes was called".to_string()
+            ),
+        ]
+    );
+}
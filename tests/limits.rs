@@ -0,0 +1,198 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::limits::{EventLimits, OverflowPolicy};
+use appmap_tracing_test::{AppMap, AppMapLayer};
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn calls(data: &AppMapObject) -> Vec<&str> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some(call.method_id.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether every call of the map has its return and every return has its call.
+fn is_paired(data: &AppMapObject) -> bool {
+    let calls: HashSet<EventId> = data
+        .events
+        .iter()
+        .filter(|x| matches!(x.event, EventObjectType::Call(_)))
+        .map(|x| x.id)
+        .collect();
+    let returned: HashSet<EventId> = data
+        .events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Return(ret) => Some(ret.parent_id),
+            _ => None,
+        })
+        .collect();
+    calls == returned
+}
+
+fn dropped_events(data: &AppMapObject) -> Option<u64> {
+    data.metadata.as_ref().and_then(|x| x.dropped_events)
+}
+
+fn limits(max_events: usize, policy: OverflowPolicy) -> EventLimits {
+    EventLimits {
+        max_events: Some(max_events),
+        max_bytes: None,
+        policy,
+    }
+}
+
+/// Records `outer`, which calls `a` to `e`, and then `after`.
+fn record(layer: &AppMapLayer) {
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!("outer").in_scope(|| {
+            for span in [
+                info_span!("a"),
+                info_span!("b"),
+                info_span!("c"),
+                info_span!("d"),
+                info_span!("e"),
+            ] {
+                span.in_scope(|| {});
+            }
+        });
+        info_span!("after").in_scope(|| {});
+    });
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}", name, std::process::id()))
+}
+
+#[test]
+fn dropping_the_oldest_events_keeps_calls_and_returns_together() {
+    let layer = AppMapLayer::new().with_limits(limits(6, OverflowPolicy::DropOldest));
+    record(&layer);
+    let data = layer.test.lock().unwrap().data.clone();
+    // the return of `outer` is dropped with its call
+    assert_eq!(calls(&data), ["d", "e", "after"]);
+    assert!(is_paired(&data));
+    assert_eq!(dropped_events(&data), Some(8));
+}
+
+#[test]
+fn dropping_the_newest_events_drops_the_returns_of_dropped_calls() {
+    let layer = AppMapLayer::new().with_limits(limits(5, OverflowPolicy::DropNewest));
+    record(&layer);
+    let data = layer.test.lock().unwrap().data.clone();
+    // the return of `outer` still fits, as its call was kept
+    assert_eq!(calls(&data), ["outer", "a", "b"]);
+    assert!(is_paired(&data));
+    assert_eq!(data.events.len(), 6);
+    assert_eq!(dropped_events(&data), Some(8));
+}
+
+#[test]
+fn dropped_spawn_events_are_not_returned() {
+    let mut app_map = AppMap::new();
+    app_map.set_limits(limits(1, OverflowPolicy::DropNewest));
+    let call = app_map.add_function_call_event(
+        1,
+        "my_app".to_string(),
+        "handle".to_string(),
+        None,
+        None,
+        true,
+        None,
+    );
+    assert_eq!(app_map.add_spawn_event(1, "my_app", "work"), None);
+    assert_eq!(app_map.data.events.len(), 1);
+    assert_eq!(app_map.data.events[0].id, call);
+}
+
+#[test]
+fn rotation_writes_the_finished_calls_to_part_files() {
+    let directory = temp_dir("rotation");
+    let layer = AppMapLayer::new().with_limits(limits(
+        4,
+        OverflowPolicy::Rotate {
+            directory: directory.clone(),
+        },
+    ));
+    record(&layer);
+    let data = layer.test.lock().unwrap().data.clone();
+
+    let part = |n: u32| {
+        AppMapObject::read_from_file(directory.join(format!("part_{}.appmap.json", n))).unwrap()
+    };
+    // `outer` stays open while its calls are rotated, without counting against the limits
+    let parts = [part(1), part(2), part(3)];
+    assert!(!directory.join("part_4.appmap.json").exists());
+    assert_eq!(calls(&parts[0]), ["a"]);
+    assert_eq!(calls(&parts[1]), ["b", "c"]);
+    assert_eq!(calls(&parts[2]), ["outer", "d", "e"]);
+    assert!(parts.iter().all(is_paired));
+    assert_eq!(calls(&data), ["after"]);
+    assert!(is_paired(&data));
+    assert_eq!(dropped_events(&data), None);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn scoped_recordings_follow_the_limits() {
+    let directory = temp_dir("scoped-rotation");
+    let layer = AppMapLayer::new().with_limits(limits(
+        4,
+        OverflowPolicy::Rotate {
+            directory: directory.clone(),
+        },
+    ));
+    let subscriber = Registry::default().with(layer.clone());
+    let data = tracing::subscriber::with_default(subscriber, || {
+        let recording = layer.start_recording("checkout");
+        for span in [info_span!("a"), info_span!("b"), info_span!("c")] {
+            span.in_scope(|| {});
+        }
+        recording.stop()
+    });
+    assert_eq!(calls(&data), ["c"]);
+    let part = directory.join("recording_1").join("part_1.appmap.json");
+    assert_eq!(
+        calls(&AppMapObject::read_from_file(part).unwrap()),
+        ["a", "b"]
+    );
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn parts_of_a_map_are_written_on_request() {
+    let directory = temp_dir("map-rotation");
+    let mut app_map = AppMap::new();
+    app_map.set_limits(limits(
+        2,
+        OverflowPolicy::Rotate {
+            directory: directory.clone(),
+        },
+    ));
+    for method in ["a", "b"] {
+        let call = app_map.add_function_call_event(
+            1,
+            "my_app".to_string(),
+            method.to_string(),
+            None,
+            None,
+            true,
+            None,
+        );
+        app_map.add_function_return_event(1, call, None, ReturnObjectType::Normal);
+    }
+    let part = directory.join("part_1.appmap.json");
+    assert!(!part.exists());
+    app_map.write_rotated_parts();
+    assert_eq!(calls(&AppMapObject::read_from_file(part).unwrap()), ["a"]);
+    assert_eq!(calls(&app_map.data), ["b"]);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
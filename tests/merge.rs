@@ -0,0 +1,145 @@
+use appmap_tracing_test::appmap_definition::merge::MergeOptions;
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMap;
+use serde_json::json;
+
+/// A map with a call of `method` of `class` on thread 1 that returns.
+fn single_call(class: &str, method: &str) -> AppMapObject {
+    let mut app_map = AppMap::new();
+    app_map.add_function_call_event(
+        1,
+        class.to_string(),
+        method.to_string(),
+        None,
+        None,
+        true,
+        None,
+    );
+    let event = json!({"id": 2, "thread_id": 1, "event": "return", "parent_id": 1, "elapsed": 0.5});
+    app_map
+        .data
+        .events
+        .push(serde_json::from_value(event).unwrap());
+    app_map.data
+}
+
+#[test]
+fn merged_events_are_renumbered() {
+    let merged = AppMapObject::merge(
+        [
+            single_call("my_app::orders", "create"),
+            single_call("my_app::orders", "cancel"),
+        ],
+        &MergeOptions::default(),
+    );
+
+    let ids: Vec<u64> = merged.events.iter().map(|x| *x.id).collect();
+    assert_eq!(ids, [1, 2, 3, 4]);
+    let parent_ids: Vec<u64> = merged
+        .events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Return(ret) => Some(*ret.parent_id),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(parent_ids, [1, 3]);
+
+    // both maps recorded on thread 1, so the second one moves to an unused thread
+    let threads: Vec<u32> = merged.events.iter().map(|x| x.thread_id).collect();
+    assert_eq!(threads, [1, 1, 2, 2]);
+}
+
+#[test]
+fn class_maps_are_merged_by_name() {
+    let merged = AppMapObject::merge(
+        [
+            single_call("my_app::orders", "create"),
+            single_call("my_app::orders", "create"),
+            single_call("my_app::orders", "cancel"),
+        ],
+        &MergeOptions::default(),
+    );
+    let stats = merged.stats();
+    let functions: Vec<(&str, usize)> = stats
+        .functions
+        .iter()
+        .map(|x| (x.method_id.as_str(), x.calls))
+        .collect();
+    assert_eq!(functions, [("create", 2), ("cancel", 1)]);
+    assert_eq!(merged.class_map.len(), 1);
+}
+
+#[test]
+fn events_can_be_ordered_by_timestamp() {
+    let mut first = single_call("my_app::orders", "create");
+    let mut second = single_call("my_app::orders", "cancel");
+    for (event, timestamp) in first.events.iter_mut().zip([1.0, 4.0]) {
+        event.timestamp = Some(timestamp);
+    }
+    for (event, timestamp) in second.events.iter_mut().zip([2.0, 3.0]) {
+        event.timestamp = Some(timestamp);
+    }
+    let merged = AppMapObject::merge(
+        [first, second],
+        &MergeOptions {
+            order_by_timestamp: true,
+        },
+    );
+    let timestamps: Vec<f64> = merged.events.iter().filter_map(|x| x.timestamp).collect();
+    assert_eq!(timestamps, [1.0, 2.0, 3.0, 4.0]);
+    let ids: Vec<u64> = merged.events.iter().map(|x| *x.id).collect();
+    assert_eq!(ids, [1, 2, 3, 4]);
+}
+
+/// The causes of every call, by method.
+fn causes(data: &AppMapObject) -> Vec<(String, Option<Vec<u64>>)> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some((
+                call.method_id.clone(),
+                call.caused_by
+                    .as_ref()
+                    .map(|ids| ids.iter().map(|x| **x).collect()),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn spawned_calls_keep_their_cause() {
+    // `handle` spawns `process`, whose second cause is not part of the map
+    let spawned: AppMapObject = serde_json::from_value(json!({
+        "version": "1.12",
+        "classMap": [],
+        "events": [
+            {
+                "id": 1, "thread_id": 1, "event": "call",
+                "defined_class": "my_app::orders", "method_id": "handle", "static": true,
+                "type": "function"
+            },
+            {"id": 2, "thread_id": 1, "event": "return", "parent_id": 1, "elapsed": 0.5},
+            {
+                "id": 3, "thread_id": 2, "event": "call", "caused_by": [1, 9],
+                "defined_class": "my_app::orders", "method_id": "process", "static": true,
+                "type": "function"
+            },
+            {"id": 4, "thread_id": 2, "event": "return", "parent_id": 3, "elapsed": 0.5}
+        ]
+    }))
+    .unwrap();
+    let merged = AppMapObject::merge(
+        [single_call("my_app::orders", "create"), spawned],
+        &MergeOptions::default(),
+    );
+    assert_eq!(
+        causes(&merged),
+        [
+            ("create".to_string(), None),
+            ("handle".to_string(), None),
+            ("process".to_string(), Some(vec![3])),
+        ]
+    );
+}
@@ -0,0 +1,235 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::convert::otlp::*;
+use serde_json::{json, Value};
+
+/// `GET /orders/7` queries the database, fails to load the order and responds with a 503, after
+/// which `notify` runs on another thread, caused by the request.
+fn sample(name: &str) -> AppMapObject {
+    serde_json::from_value(json!({
+        "version": "1.12",
+        "metadata": {"name": name, "language": {"name": "rust"}},
+        "classMap": [],
+        "events": [
+            {
+                "id": 1, "thread_id": 1, "timestamp": 1714564800.0, "event": "call",
+                "defined_class": "my_app::http", "method_id": "handle", "static": true,
+                "path": "src/http.rs", "lineno": 12,
+                "parameters": [{"name": "id", "class": "u64", "value": "7"}],
+                "type": "httpServerRequest",
+                "http_server_request": {
+                    "request_method": "GET",
+                    "path_info": "/orders/7",
+                    "normalized_path_info": "/orders/{id}",
+                    "protocol": "HTTP/1.1",
+                    "headers": {"Accept": "application/json"}
+                }
+            },
+            {
+                "id": 2, "thread_id": 1, "timestamp": 1714564800.1, "event": "call",
+                "defined_class": "my_app::db", "method_id": "query", "static": true,
+                "type": "sqlQuery",
+                "sql_query": {"database_type": "postgresql", "sql": "SELECT * FROM orders"}
+            },
+            {"id": 3, "thread_id": 1, "timestamp": 1714564800.3, "event": "return", "parent_id": 2, "elapsed": 0.2},
+            {
+                "id": 4, "thread_id": 1, "timestamp": 1714564800.4, "event": "call",
+                "defined_class": "my_app::orders", "method_id": "load", "static": true,
+                "type": "function"
+            },
+            {
+                "id": 5, "thread_id": 1, "timestamp": 1714564800.5, "event": "return", "parent_id": 4,
+                "elapsed": 0.1,
+                "exceptions": [{"class": "panic", "message": "not found", "object_id": 1}]
+            },
+            {
+                "id": 6, "thread_id": 1, "timestamp": 1714564800.6, "event": "call",
+                "defined_class": "my_app::http", "method_id": "respond", "static": true,
+                "type": "httpServerResponse",
+                "http_server_response": {"status": 503}
+            },
+            {"id": 7, "thread_id": 1, "timestamp": 1714564800.6, "event": "return", "parent_id": 6, "elapsed": 0.0},
+            {
+                "id": 8, "thread_id": 1, "timestamp": 1714564801.0, "event": "return", "parent_id": 1,
+                "elapsed": 1.0,
+                "return_value": {"class": "u64", "value": "503"}
+            },
+            {
+                "id": 9, "thread_id": 2, "timestamp": 1714564802.0, "event": "call",
+                "defined_class": "my_app::orders", "method_id": "notify", "static": true,
+                "caused_by": [1],
+                "type": "function"
+            },
+            {"id": 10, "thread_id": 2, "timestamp": 1714564802.5, "event": "return", "parent_id": 9, "elapsed": 0.5}
+        ]
+    }))
+    .unwrap()
+}
+
+fn spans(traces: &OtlpTraces) -> &[OtlpSpan] {
+    &traces.resource_spans[0].scope_spans[0].spans
+}
+
+fn span<'a>(traces: &'a OtlpTraces, name: &str) -> &'a OtlpSpan {
+    spans(traces).iter().find(|x| x.name == name).unwrap()
+}
+
+fn attribute<'a>(span: &'a OtlpSpan, key: &str) -> Option<&'a AnyValue> {
+    span.attributes
+        .iter()
+        .find(|x| x.key == key)
+        .map(|x| &x.value)
+}
+
+fn string(value: &str) -> Option<AnyValue> {
+    Some(AnyValue::StringValue(value.to_string()))
+}
+
+fn int(value: u64) -> Option<AnyValue> {
+    Some(AnyValue::IntValue(value.to_string()))
+}
+
+#[test]
+fn traces_have_the_json_shape_of_otlp() {
+    let traces = to_otlp(&sample("checkout"), &OtlpOptions::default());
+    let json = serde_json::to_value(&traces).unwrap();
+    let resource = &json["resourceSpans"][0];
+    assert_eq!(
+        resource["resource"]["attributes"],
+        json!([
+            {"key": "service.name", "value": {"stringValue": "checkout"}},
+            {"key": "telemetry.sdk.language", "value": {"stringValue": "rust"}},
+        ])
+    );
+    assert_eq!(
+        resource["scopeSpans"][0]["scope"]["name"],
+        "appmap_tracing_test"
+    );
+    let root = &resource["scopeSpans"][0]["spans"][0];
+    assert_eq!(root["name"], "GET /orders/{id}");
+    assert_eq!(root["kind"], SPAN_KIND_SERVER);
+    assert_eq!(root["startTimeUnixNano"], "1714564800000000000");
+    assert_eq!(root["endTimeUnixNano"], "1714564801000000000");
+    assert!(root.get("parentSpanId").is_none());
+    for (key, length) in [("traceId", 32), ("spanId", 16)] {
+        let id = root[key].as_str().unwrap();
+        assert_eq!(id.len(), length);
+        assert!(id.chars().all(|x| x.is_ascii_hexdigit()), "{}", id);
+    }
+    assert_eq!(
+        root["attributes"][3],
+        json!({"key": "code.lineno", "value": {"intValue": "12"}})
+    );
+    let child = &resource["scopeSpans"][0]["spans"][1];
+    assert_eq!(child["parentSpanId"], root["spanId"]);
+    assert_eq!(child["traceId"], root["traceId"]);
+    assert_eq!(child.get("status"), None::<&Value>);
+}
+
+#[test]
+fn http_and_sql_calls_have_semantic_convention_attributes() {
+    let traces = to_otlp(&sample("checkout"), &OtlpOptions::default());
+    let request = span(&traces, "GET /orders/{id}");
+    assert_eq!(
+        attribute(request, "http.request.method").cloned(),
+        string("GET")
+    );
+    assert_eq!(attribute(request, "url.path").cloned(), string("/orders/7"));
+    assert_eq!(
+        attribute(request, "http.route").cloned(),
+        string("/orders/{id}")
+    );
+    assert_eq!(
+        attribute(request, "network.protocol.name").cloned(),
+        string("http")
+    );
+    assert_eq!(
+        attribute(request, "network.protocol.version").cloned(),
+        string("1.1")
+    );
+    assert_eq!(
+        attribute(request, "http.request.header.accept").cloned(),
+        Some(AnyValue::ArrayValue(ArrayValue {
+            values: vec![AnyValue::StringValue("application/json".to_string())]
+        }))
+    );
+    assert_eq!(attribute(request, "id").cloned(), int(7));
+    assert_eq!(attribute(request, "return_value").cloned(), int(503));
+
+    let query = span(&traces, "SELECT * FROM orders");
+    assert_eq!(query.kind, SPAN_KIND_CLIENT);
+    assert_eq!(attribute(query, "db.system").cloned(), string("postgresql"));
+    assert_eq!(
+        attribute(query, "db.query.text").cloned(),
+        string("SELECT * FROM orders")
+    );
+
+    let response = span(&traces, "503");
+    assert_eq!(
+        attribute(response, "http.response.status_code").cloned(),
+        int(503)
+    );
+}
+
+#[test]
+fn exceptions_and_http_errors_have_an_error_status() {
+    let traces = to_otlp(&sample("checkout"), &OtlpOptions::default());
+    let load = span(&traces, "load");
+    let status = load.status.as_ref().unwrap();
+    assert_eq!(status.code, STATUS_CODE_ERROR);
+    assert_eq!(status.message.as_deref(), Some("panic: not found"));
+    assert_eq!(load.events.len(), 1);
+    assert_eq!(load.events[0].name, "exception");
+    assert_eq!(load.events[0].time_unix_nano, load.end_time_unix_nano);
+
+    let response = span(&traces, "503");
+    assert_eq!(response.status.as_ref().unwrap().code, STATUS_CODE_ERROR);
+    assert_eq!(span(&traces, "GET /orders/{id}").status, None);
+}
+
+#[test]
+fn causes_become_links_to_other_traces() {
+    let traces = to_otlp(&sample("checkout"), &OtlpOptions::default());
+    let request = span(&traces, "GET /orders/{id}");
+    let notify = span(&traces, "notify");
+    assert_ne!(notify.trace_id, request.trace_id);
+    assert_eq!(notify.parent_span_id, None);
+    assert_eq!(
+        notify.links,
+        [SpanLink {
+            trace_id: request.trace_id.clone(),
+            span_id: request.span_id.clone(),
+        }]
+    );
+}
+
+#[test]
+fn ids_depend_on_the_map() {
+    let ids = |traces: &OtlpTraces| -> Vec<(String, String)> {
+        spans(traces)
+            .iter()
+            .map(|x| (x.trace_id.clone(), x.span_id.clone()))
+            .collect()
+    };
+    let checkout = ids(&to_otlp(&sample("checkout"), &OtlpOptions::default()));
+    assert_eq!(
+        checkout,
+        ids(&to_otlp(&sample("checkout"), &OtlpOptions::default()))
+    );
+    let refund = ids(&to_otlp(&sample("refund"), &OtlpOptions::default()));
+    for (checkout, refund) in checkout.iter().zip(refund.iter()) {
+        assert_ne!(checkout.0, refund.0);
+        assert_ne!(checkout.1, refund.1);
+    }
+
+    let options = OtlpOptions {
+        seed: Some(7),
+        ..Default::default()
+    };
+    assert_eq!(
+        ids(&to_otlp(&sample("checkout"), &options)),
+        ids(&to_otlp(&sample("refund"), &options))
+    );
+    // ids of different calls stay different
+    let span_ids: std::collections::HashSet<&String> = checkout.iter().map(|x| &x.1).collect();
+    assert_eq!(span_ids.len(), checkout.len());
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use appmap_tracing_test::appmap_definition::prune::PruneOptions;
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMap;
+
+use common::call;
+
+fn calls(data: &AppMapObject) -> Vec<String> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some(call.method_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn returns(data: &AppMapObject) -> usize {
+    data.events
+        .iter()
+        .filter(|x| matches!(x.event, EventObjectType::Return(_)))
+        .count()
+}
+
+/// `create` calls `log` three times and `save` once, which calls `log` again.
+fn sample() -> AppMapObject {
+    let mut app_map = AppMap::new();
+    call(&mut app_map, 1, "create", 0.1, |app_map| {
+        for _ in 0..3 {
+            call(app_map, 1, "log", 0.1, |_| {});
+        }
+        call(app_map, 1, "save", 0.1, |app_map| {
+            call(app_map, 1, "log", 0.1, |_| {})
+        });
+    });
+    app_map.data
+}
+
+#[test]
+fn frequent_leaf_functions_are_removed() {
+    let mut data = sample();
+    data.prune(&PruneOptions {
+        max_leaf_calls: Some(3),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(calls(&data), ["create", "save"]);
+    assert_eq!(returns(&data), 2);
+    let class_map = serde_json::to_string(&data.class_map).unwrap();
+    assert!(!class_map.contains("\"log\""), "{}", class_map);
+}
+
+#[test]
+fn repeated_subtrees_are_cut_down() {
+    let mut data = sample();
+    data.prune(&PruneOptions {
+        max_repeated_subtrees: Some(2),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(calls(&data), ["create", "log", "log", "save", "log"]);
+    assert_eq!(returns(&data), 5);
+}
+
+#[test]
+fn the_most_called_functions_are_removed_until_the_map_fits() {
+    let data = sample();
+    let mut expected = data.clone();
+    expected
+        .prune(&PruneOptions {
+            max_leaf_calls: Some(0),
+            ..Default::default()
+        })
+        .unwrap();
+    let max_size = serde_json::to_vec(&expected).unwrap().len();
+
+    let mut data = data;
+    data.prune(&PruneOptions {
+        max_size: Some(max_size),
+        ..Default::default()
+    })
+    .unwrap();
+    // removing `log` from the class map as well makes the map fit, so `save` stays
+    assert_eq!(calls(&data), ["create", "save"]);
+    assert_eq!(data, expected);
+    assert!(serde_json::to_vec(&data).unwrap().len() <= max_size);
+}
+
+#[test]
+fn everything_is_removed_if_nothing_fits() {
+    let mut data = sample();
+    data.prune(&PruneOptions {
+        max_size: Some(1),
+        ..Default::default()
+    })
+    .unwrap();
+    assert!(data.events.is_empty());
+    assert!(data.class_map.is_empty());
+}
+
+/// The call of `save` in `data`.
+fn save(data: &mut AppMapObject) -> &mut CallObject {
+    data.events
+        .iter_mut()
+        .find_map(|x| match &mut x.event {
+            EventObjectType::Call(call) if call.method_id == "save" => Some(call),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn causes_that_were_removed_are_dropped() {
+    // the first call of `log` has the id 2
+    let (create, log) = (EventId::from(1), EventId::from(2));
+    for (caused_by, remaining) in [(vec![create, log], Some(vec![create])), (vec![log], None)] {
+        let mut data = sample();
+        save(&mut data).caused_by = Some(caused_by);
+        data.prune(&PruneOptions {
+            max_leaf_calls: Some(3),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(save(&mut data).caused_by, remaining);
+    }
+}
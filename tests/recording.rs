@@ -0,0 +1,129 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMapLayer;
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn calls(data: &AppMapObject) -> Vec<&str> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some(call.method_id.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn returns(data: &AppMapObject) -> usize {
+    data.events
+        .iter()
+        .filter(|x| matches!(x.event, EventObjectType::Return(_)))
+        .count()
+}
+
+#[test]
+fn recordings_contain_the_calls_made_while_they_run() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    let data = tracing::subscriber::with_default(subscriber, || {
+        info_span!("before").in_scope(|| {});
+        let recording = layer.start_recording("checkout");
+        info_span!("create").in_scope(|| {
+            info_span!("validate").in_scope(|| {});
+        });
+        let data = recording.stop();
+        info_span!("after").in_scope(|| {});
+        data
+    });
+
+    assert_eq!(calls(&data), ["create", "validate"]);
+    assert_eq!(returns(&data), 2);
+    let metadata = data.metadata.unwrap();
+    assert_eq!(metadata.name.as_deref(), Some("checkout"));
+    assert_eq!(metadata.recorder.unwrap().type_.as_deref(), Some("process"));
+    // the process wide recording is not affected
+    let default_map = &layer.test.lock().unwrap().data;
+    assert_eq!(
+        calls(default_map),
+        ["before", "create", "validate", "after"]
+    );
+}
+
+#[test]
+fn span_recordings_nest() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    let (everything, request, handler) = tracing::subscriber::with_default(subscriber, || {
+        let everything = layer.start_recording("everything");
+        let request_span = info_span!("request");
+        let request = layer.start_recording_in_span("request", &request_span);
+        let handler = request_span.in_scope(|| {
+            info_span!("authenticate").in_scope(|| {});
+            let handler_span = info_span!("handler");
+            let handler = layer.start_recording_in_span("handler", &handler_span);
+            handler_span.in_scope(|| {
+                info_span!("query").in_scope(|| {});
+            });
+            info_span!("respond").in_scope(|| {});
+            handler
+        });
+        drop(request_span);
+        info_span!("unrelated").in_scope(|| {});
+        (everything.stop(), request.stop(), handler.stop())
+    });
+
+    assert_eq!(
+        calls(&everything),
+        [
+            "request",
+            "authenticate",
+            "handler",
+            "query",
+            "respond",
+            "unrelated"
+        ]
+    );
+    assert_eq!(
+        calls(&request),
+        ["request", "authenticate", "handler", "query", "respond"]
+    );
+    assert_eq!(returns(&request), 5);
+    assert_eq!(calls(&handler), ["handler", "query"]);
+    assert_eq!(returns(&handler), 2);
+    let metadata = handler.metadata.unwrap();
+    assert_eq!(
+        metadata.recorder.unwrap().type_.as_deref(),
+        Some("requests")
+    );
+}
+
+#[test]
+fn span_recordings_end_when_the_span_is_closed() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    let data = tracing::subscriber::with_default(subscriber, || {
+        let request_span = info_span!("request");
+        let request = layer.start_recording_in_span("request", &request_span);
+        request_span.in_scope(|| {});
+        drop(request_span);
+        // the registry may hand the slot of the closed span to the next one
+        info_span!("next_request").in_scope(|| {});
+        request.stop()
+    });
+    assert_eq!(calls(&data), ["request"]);
+    assert_eq!(returns(&data), 1);
+}
+
+#[test]
+fn dropped_recordings_record_nothing_more() {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        let recording = layer.start_recording("discarded");
+        info_span!("create").in_scope(|| {});
+        drop(recording);
+        let recording = layer.start_recording("kept");
+        info_span!("cancel").in_scope(|| {});
+        assert_eq!(calls(&recording.stop()), ["cancel"]);
+    });
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "remote-recording")]
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::remote_recording::{router, RecordingStatus, RECORD_PATH};
+use appmap_tracing_test::AppMapLayer;
+use axum::body::{to_bytes, Body};
+use axum::http::{Method, Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[tracing::instrument]
+fn handle_request() {}
+
+async fn send(app: &Router, method: Method) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(RECORD_PATH)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, body.to_vec())
+}
+
+async fn enabled(app: &Router) -> bool {
+    let (status, body) = send(app, Method::GET).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice::<RecordingStatus>(&body)
+        .unwrap()
+        .enabled
+}
+
+#[tokio::test]
+async fn start_status_and_stop() {
+    let layer = AppMapLayer::new();
+    let _default = tracing::subscriber::set_default(Registry::default().with(layer.clone()));
+    let app = router(layer);
+
+    handle_request();
+    assert!(!enabled(&app).await);
+    assert_eq!(send(&app, Method::DELETE).await.0, StatusCode::NOT_FOUND);
+
+    assert_eq!(send(&app, Method::POST).await.0, StatusCode::OK);
+    assert!(enabled(&app).await);
+    assert_eq!(send(&app, Method::POST).await.0, StatusCode::CONFLICT);
+    handle_request();
+
+    let (status, body) = send(&app, Method::DELETE).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!enabled(&app).await);
+    let map: AppMapObject = serde_json::from_slice(&body).unwrap();
+    let calls: Vec<_> = map
+        .events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some(call.method_id.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(calls, ["handle_request"]);
+    let recorder = map.metadata.unwrap().recorder.unwrap();
+    assert_eq!(recorder.type_.as_deref(), Some("remote"));
+}
@@ -0,0 +1,276 @@
+use appmap_tracing_test::appmap_definition::rules::{CallMatcher, RuleConfig, RuleSet, Severity};
+use appmap_tracing_test::appmap_definition::*;
+use serde_json::{json, Value};
+
+fn call(id: u64, class: &str, method: &str) -> Value {
+    json!({
+        "id": id, "thread_id": 1, "event": "call",
+        "defined_class": class, "method_id": method, "static": true,
+        "type": "function"
+    })
+}
+
+fn query(id: u64, sql: &str) -> Value {
+    json!({
+        "id": id, "thread_id": 1, "event": "call",
+        "defined_class": "my_app::db", "method_id": "query", "static": true,
+        "type": "sqlQuery",
+        "sql_query": {"database_type": "postgresql", "sql": sql}
+    })
+}
+
+fn ret(id: u64, parent_id: u64, elapsed: f64) -> Value {
+    json!({"id": id, "thread_id": 1, "event": "return", "parent_id": parent_id, "elapsed": elapsed})
+}
+
+/// `handle` logs a token, uses another token itself and runs a slow and a fast query, after
+/// which a token is used by a root call.
+fn sample() -> AppMapObject {
+    let function = |name: &str, labels: &[&str]| json!({"type": "function", "name": name, "static": true, "labels": labels});
+    serde_json::from_value(json!({
+        "version": "1.12",
+        "metadata": {"name": "login"},
+        "classMap": [{
+            "type": "package", "name": "my_app",
+            "children": [
+                {"type": "class", "name": "logger", "children": [function("log", &["log"])]},
+                {"type": "class", "name": "auth", "children": [function("token", &["secret"])]},
+            ]
+        }],
+        "events": [
+            call(1, "my_app::http", "handle"),
+            call(2, "my_app::logger", "log"),
+            call(3, "my_app::auth", "token"),
+            ret(4, 3, 0.01),
+            ret(5, 2, 0.02),
+            call(6, "my_app::auth", "token"),
+            ret(7, 6, 0.01),
+            query(8, "SELECT * FROM users"),
+            ret(9, 8, 0.8),
+            query(10, "SELECT * FROM sessions"),
+            ret(11, 10, 0.1),
+            ret(12, 1, 1.0),
+            call(13, "my_app::auth", "token"),
+            ret(14, 13, 0.01),
+        ]
+    }))
+    .unwrap()
+}
+
+fn labels(labels: &[&str]) -> CallMatcher {
+    CallMatcher {
+        labels: labels.iter().map(|x| x.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn function(function: &str) -> CallMatcher {
+    CallMatcher {
+        function: Some(function.to_string()),
+        ..Default::default()
+    }
+}
+
+fn sql_query() -> CallMatcher {
+    CallMatcher {
+        type_: Some("sql_query".to_string()),
+        ..Default::default()
+    }
+}
+
+fn rule(match_: CallMatcher) -> RuleConfig {
+    RuleConfig {
+        id: "rule".to_string(),
+        match_,
+        ..Default::default()
+    }
+}
+
+/// Every finding as "<event id> in <scope id>: <message>".
+fn check(rules: Vec<RuleConfig>) -> Vec<String> {
+    sample()
+        .check_rules(&RuleSet { rules })
+        .into_iter()
+        .map(|x| format!("{} in {}: {}", *x.event.id, *x.scope.id, x.message))
+        .collect()
+}
+
+fn temp_file(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.yml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn the_example_rules_find_the_logged_secret_and_the_slow_query() {
+    let rules = RuleSet::load("tests/fixtures/rules.yml").unwrap();
+    let findings = sample().check_rules(&rules);
+    let summary: Vec<(&str, Option<Severity>, u64, &str)> = findings
+        .iter()
+        .map(|x| {
+            (
+                x.rule_id.as_str(),
+                x.severity,
+                *x.event.id,
+                x.message.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                "secret-in-log",
+                Some(Severity::Error),
+                3,
+                "my_app::auth::token was called under my_app::logger::log"
+            ),
+            (
+                "slow-query",
+                Some(Severity::Warning),
+                8,
+                "SELECT * FROM users took 0.800s"
+            ),
+        ]
+    );
+    let secret = &findings[0];
+    assert_eq!(secret.rule_title, "Secret used while logging");
+    assert_eq!(secret.impact_domain, "Security");
+    assert_eq!(*secret.scope.id, 2);
+    let related: Vec<u64> = secret.related_events.iter().map(|x| *x.id).collect();
+    assert_eq!(related, [3, 2]);
+    // the slow query has no related call and is scoped to its root call
+    assert_eq!(*findings[1].scope.id, 1);
+}
+
+#[test]
+fn not_ancestor_reports_calls_outside_of_the_matching_calls() {
+    let findings = check(vec![RuleConfig {
+        not_ancestor: Some(labels(&["log"])),
+        ..rule(labels(&["secret"]))
+    }]);
+    // both calls of `token` give the same finding
+    assert_eq!(
+        findings,
+        ["6 in 1: my_app::auth::token was not called under a call labeled log"]
+    );
+    let findings = check(vec![RuleConfig {
+        not_ancestor: Some(CallMatcher {
+            type_: Some("function".to_string()),
+            function: Some("my_app::http".to_string()),
+            ..Default::default()
+        }),
+        ..rule(labels(&["secret"]))
+    }]);
+    assert_eq!(
+        findings,
+        ["13 in 13: my_app::auth::token was not called under a function call of my_app::http"]
+    );
+}
+
+#[test]
+fn child_and_descendant_look_below_the_call() {
+    let findings = check(vec![
+        RuleConfig {
+            child: Some(labels(&["secret"])),
+            ..rule(function("my_app::http"))
+        },
+        RuleConfig {
+            descendant: Some(sql_query()),
+            ..rule(function("my_app::http"))
+        },
+        // `log` calls no query
+        RuleConfig {
+            descendant: Some(sql_query()),
+            ..rule(labels(&["log"]))
+        },
+    ]);
+    assert_eq!(
+        findings,
+        [
+            // `log` is the first child, the `token` it calls is not a child of `handle`
+            "1 in 6: my_app::http::handle called my_app::auth::token",
+            "1 in 8: my_app::http::handle led to SELECT * FROM users",
+        ]
+    );
+    // `log` calls `token`, not the other way round
+    let findings = check(vec![RuleConfig {
+        child: Some(labels(&["log"])),
+        ..rule(labels(&["secret"]))
+    }]);
+    assert!(findings.is_empty(), "{:?}", findings);
+}
+
+#[test]
+fn min_elapsed_includes_the_limit() {
+    let findings = check(vec![rule(CallMatcher {
+        min_elapsed: Some(0.1),
+        ..sql_query()
+    })]);
+    assert_eq!(
+        findings,
+        [
+            "8 in 1: SELECT * FROM users took 0.800s",
+            "10 in 1: SELECT * FROM sessions took 0.100s",
+        ]
+    );
+    // calls without a return have no elapsed time
+    let mut data = sample();
+    data.events.retain(|x| *x.id != 9);
+    let rules = RuleSet {
+        rules: vec![rule(CallMatcher {
+            min_elapsed: Some(0.5),
+            ..sql_query()
+        })],
+    };
+    assert!(data.check_rules(&rules).is_empty());
+}
+
+#[test]
+fn rule_sets_with_duplicate_or_empty_ids_are_rejected() {
+    let path = temp_file(
+        "duplicate-rules",
+        "rules:\n  - id: slow\n    match: {type: sql_query}\n  - id: slow\n    match: {}\n",
+    );
+    let error = RuleSet::load(&path).unwrap_err();
+    assert_eq!(error.to_string(), "rule slow is defined more than once");
+    std::fs::remove_file(&path).unwrap();
+
+    let path = temp_file("empty-rule-id", "rules:\n  - id: ''\n    match: {}\n");
+    assert_eq!(
+        RuleSet::load(&path).unwrap_err().to_string(),
+        "a rule has an empty id"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn findings_with_the_same_hash_are_merged() {
+    let findings = sample().check_rules(&RuleSet {
+        rules: vec![
+            rule(labels(&["secret"])),
+            RuleConfig {
+                id: "secret-under-log".to_string(),
+                ancestor: Some(labels(&["log"])),
+                ..rule(labels(&["secret"]))
+            },
+        ],
+    });
+    let summary: Vec<(&str, usize, Vec<u64>)> = findings
+        .iter()
+        .map(|x| {
+            (
+                x.rule_id.as_str(),
+                x.occurrence_count,
+                x.related_events.iter().map(|x| *x.id).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("rule", 3, vec![3, 6, 13]),
+            ("secret-under-log", 1, vec![3, 2]),
+        ]
+    );
+}
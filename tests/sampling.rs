@@ -0,0 +1,116 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::config::SamplingConfig;
+use appmap_tracing_test::sampling::Sampler;
+use appmap_tracing_test::AppMapLayer;
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn calls(data: &AppMapObject) -> Vec<&str> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Call(call) => Some(call.method_id.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn unrecorded_calls(data: &AppMapObject, name: &str) -> Option<u64> {
+    fn find(node: &CodeObjectType, name: &str) -> Option<Option<u64>> {
+        match node {
+            CodeObjectType::Package(x) => x.children.iter().flatten().find_map(|x| find(x, name)),
+            CodeObjectType::Class(x) => x.children.iter().flatten().find_map(|x| find(x, name)),
+            CodeObjectType::Function(x) => Some(x.unrecorded_calls).filter(|_| x.name == name),
+        }
+    }
+    data.class_map.iter().find_map(|x| find(x, name)).unwrap()
+}
+
+fn record(sampler: Sampler, run: impl FnOnce()) -> AppMapObject {
+    let layer = AppMapLayer::new().with_sampler(sampler);
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, run);
+    let data = layer.test.lock().unwrap().data.clone();
+    data
+}
+
+/// Handles three requests, each querying twice, and fetching a row in every query.
+fn handle_requests() {
+    for request in [
+        info_span!("first"),
+        info_span!("second"),
+        info_span!("third"),
+    ] {
+        request.in_scope(|| {
+            for _ in 0..2 {
+                info_span!("query").in_scope(|| info_span!("fetch").in_scope(|| {}));
+            }
+        });
+    }
+}
+
+#[test]
+fn one_in_rate_root_spans_is_recorded_with_its_whole_tree() {
+    let data = record(Sampler::new(Some(2), None), handle_requests);
+    assert_eq!(
+        calls(&data),
+        [
+            "first", "query", "fetch", "query", "fetch", "third", "query", "fetch", "query",
+            "fetch"
+        ]
+    );
+    // calls of trees that were not sampled are not counted
+    assert_eq!(unrecorded_calls(&data, "query"), None);
+}
+
+#[test]
+fn calls_over_the_cap_are_counted_with_the_calls_below_them() {
+    let data = record(Sampler::new(None, Some(3)), handle_requests);
+    assert_eq!(
+        calls(&data),
+        ["first", "query", "fetch", "query", "fetch", "second", "query", "fetch", "third"]
+    );
+    assert_eq!(unrecorded_calls(&data, "query"), Some(3));
+    assert_eq!(unrecorded_calls(&data, "fetch"), Some(3));
+    assert_eq!(unrecorded_calls(&data, "first"), None);
+
+    let stats = data.stats();
+    let stats_of = |name: &str| {
+        stats
+            .functions
+            .iter()
+            .find(|x| x.method_id == name)
+            .unwrap()
+    };
+    assert_eq!(stats_of("query").calls, 3);
+    assert_eq!(stats_of("query").unrecorded_calls, 3);
+    assert_eq!(stats_of("fetch").unrecorded_calls, 3);
+    assert_eq!(stats_of("first").unrecorded_calls, 0);
+}
+
+#[test]
+fn spans_entered_several_times_are_counted_once() {
+    let data = record(Sampler::new(None, Some(1)), || {
+        for _ in 0..2 {
+            // like the span of a future, which is entered on every poll
+            let span = info_span!("poll");
+            let step = info_span!(parent: &span, "step");
+            for _ in 0..3 {
+                span.in_scope(|| step.in_scope(|| {}));
+            }
+        }
+    });
+    assert_eq!(calls(&data), ["poll", "step"]);
+    assert_eq!(unrecorded_calls(&data, "poll"), Some(1));
+    assert_eq!(unrecorded_calls(&data, "step"), Some(1));
+}
+
+#[test]
+fn a_sampling_rate_of_zero_is_rejected() {
+    let config = SamplingConfig {
+        rate: Some(0),
+        max_calls_per_function: None,
+    };
+    assert!(Sampler::from_config(&config).is_err());
+}
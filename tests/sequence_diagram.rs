@@ -0,0 +1,135 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::convert::sequence_diagram::{
+    to_sequence_diagram, ActorGrouping, SequenceDiagramOptions,
+};
+use appmap_tracing_test::AppMap;
+use serde_json::json;
+
+fn call(
+    app_map: &mut AppMap,
+    class: &str,
+    method: &str,
+    type_: CallObjectType,
+    children: impl FnOnce(&mut AppMap),
+) {
+    app_map.add_function_call_event(
+        1,
+        class.to_string(),
+        method.to_string(),
+        None,
+        None,
+        true,
+        None,
+    );
+    let call = app_map.data.events.last_mut().unwrap();
+    let parent_id = *call.id;
+    if let EventObjectType::Call(call) = &mut call.event {
+        call.type_ = type_;
+    }
+    children(app_map);
+    let id = app_map.get_next_event_id();
+    let event = json!({
+        "id": id, "thread_id": 1, "event": "return", "parent_id": parent_id, "elapsed": 0.1,
+    });
+    app_map
+        .data
+        .events
+        .push(serde_json::from_value(event).unwrap());
+}
+
+fn query() -> CallObjectType {
+    CallObjectType::SqlQuery(SqlQueryCallObject {
+        sql_query: SqlQueryObject {
+            database_type: "sqlite".to_string(),
+            sql: "INSERT INTO orders; SELECT last_insert_rowid()".to_string(),
+            explain_sql: None,
+            server_version: None,
+        },
+    })
+}
+
+/// `create` validates the order itself and saves it three times, running a query each time.
+fn sample() -> AppMapObject {
+    let mut app_map = AppMap::new();
+    call(
+        &mut app_map,
+        "my_app::orders",
+        "create",
+        CallObjectType::Function,
+        |app_map| {
+            call(
+                app_map,
+                "my_app::orders",
+                "validate",
+                CallObjectType::Function,
+                |_| {},
+            );
+            for _ in 0..3 {
+                call(
+                    app_map,
+                    "my_app::db::Repository",
+                    "save",
+                    CallObjectType::Function,
+                    |app_map| call(app_map, "my_app::db", "query", query(), |_| {}),
+                );
+            }
+        },
+    );
+    app_map.data
+}
+
+#[test]
+fn calls_are_written_as_plantuml() {
+    let diagram = to_sequence_diagram(&sample(), &SequenceDiagramOptions::default());
+    assert_eq!(
+        diagram.to_plantuml(),
+        "@startuml\n\
+         actor \"caller\" as a0\n\
+         participant \"my_app::orders\" as a1\n\
+         participant \"my_app::db::Repository\" as a2\n\
+         database \"Database\" as a3\n\
+         a0 -> a1 : create\n\
+         a1 -> a1 : validate\n\
+         loop 3 times\n\
+         a1 -> a2 : save\n\
+         a2 -> a3 : INSERT INTO orders; SELECT last_insert_rowid()\n\
+         a3 --> a2 : return\n\
+         a2 --> a1 : return\n\
+         end\n\
+         a1 --> a0 : return\n\
+         @enduml\n"
+    );
+}
+
+#[test]
+fn calls_are_written_as_mermaid() {
+    let diagram = to_sequence_diagram(&sample(), &SequenceDiagramOptions::default());
+    assert_eq!(
+        diagram.to_mermaid(),
+        "sequenceDiagram\n    \
+         actor a0 as caller\n    \
+         participant a1 as my_app::orders\n    \
+         participant a2 as my_app::db::Repository\n    \
+         participant a3 as Database\n    \
+         a0->>a1: create\n    \
+         a1->>a1: validate\n    \
+         loop 3 times\n        \
+         a1->>a2: save\n        \
+         a2->>a3: INSERT INTO orders#59; SELECT last_insert_rowid()\n        \
+         a3-->>a2: return\n        \
+         a2-->>a1: return\n    \
+         end\n    \
+         a1-->>a0: return\n"
+    );
+}
+
+#[test]
+fn classes_can_be_grouped_by_package() {
+    let options = SequenceDiagramOptions {
+        grouping: ActorGrouping::Package,
+    };
+    let diagram = to_sequence_diagram(&sample(), &options);
+    let actors: Vec<&str> = diagram.actors.iter().map(|x| x.name.as_str()).collect();
+    // the class map has no packages, so the classes are grouped by their top level module
+    assert_eq!(actors, ["caller", "my_app", "Database"]);
+}
@@ -0,0 +1,80 @@
+use appmap_tracing_test::appmap_definition::source_info::{SourceCache, SourceOptions};
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMap;
+
+/// The functions of `tests/fixtures/orders.rs` located at their `#[instrument]` attribute, as
+/// recorded, and a function of a file that does not parse.
+fn sample() -> AppMapObject {
+    let mut app_map = AppMap::new();
+    let orders = std::path::Path::new("tests/fixtures/orders.rs");
+    app_map.add_function_to_class_map("my_app::orders", "create", Some(orders), Some(6));
+    app_map.add_function_to_class_map("my_app::orders::Orders", "cancel", Some(orders), Some(15));
+    let broken = std::path::Path::new("tests/fixtures/broken.rs");
+    app_map.add_function_to_class_map("my_app::broken", "broken", Some(broken), Some(1));
+    app_map.data
+}
+
+fn function<'a>(data: &'a AppMapObject, name: &str) -> &'a FunctionCodeObject {
+    fn find<'a>(node: &'a CodeObjectType, name: &str) -> Option<&'a FunctionCodeObject> {
+        match node {
+            CodeObjectType::Package(x) => x.children.iter().flatten().find_map(|x| find(x, name)),
+            CodeObjectType::Class(x) => x.children.iter().flatten().find_map(|x| find(x, name)),
+            CodeObjectType::Function(x) => Some(x).filter(|x| x.name == name),
+        }
+    }
+    data.class_map.iter().find_map(|x| find(x, name)).unwrap()
+}
+
+#[test]
+fn comments_source_and_location_are_taken_from_the_source_file() {
+    let mut data = sample();
+    data.add_source_info(&mut SourceCache::new("."), &SourceOptions::default());
+
+    let create = function(&data, "create");
+    assert_eq!(
+        create.location.as_deref(),
+        Some("tests/fixtures/orders.rs:7")
+    );
+    assert_eq!(
+        create.comment.as_deref(),
+        Some("Creates an order.\n\nFails if the order is invalid.")
+    );
+    assert_eq!(
+        create.source.as_deref(),
+        Some("pub fn create(id: u64) -> u64 {\n    id\n}")
+    );
+
+    let cancel = function(&data, "cancel");
+    assert_eq!(
+        cancel.location.as_deref(),
+        Some("tests/fixtures/orders.rs:16")
+    );
+    assert_eq!(cancel.comment.as_deref(), Some("Cancels the order."));
+    assert_eq!(cancel.source.as_deref(), Some("    fn cancel(&self) {}"));
+}
+
+#[test]
+fn functions_of_files_that_do_not_parse_are_unchanged() {
+    let mut data = sample();
+    let expected = function(&data, "broken").clone();
+    data.add_source_info(&mut SourceCache::new("."), &SourceOptions::default());
+    assert_eq!(function(&data, "broken"), &expected);
+}
+
+#[test]
+fn only_the_selected_information_is_added() {
+    let mut data = sample();
+    let options = SourceOptions {
+        comment: true,
+        source: false,
+        fix_location: false,
+    };
+    data.add_source_info(&mut SourceCache::new("."), &options);
+    let create = function(&data, "create");
+    assert_eq!(
+        create.location.as_deref(),
+        Some("tests/fixtures/orders.rs:6")
+    );
+    assert!(create.comment.is_some());
+    assert_eq!(create.source, None);
+}
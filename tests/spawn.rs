@@ -0,0 +1,149 @@
+use std::future::Future;
+use std::sync::mpsc;
+use std::task::Poll;
+
+use appmap_tracing_test::appmap_definition::call_tree::CallTree;
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMapLayer;
+use tracing::instrument::WithSubscriber;
+use tracing::{info_span, Instrument};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// The map recorded while running `run`.
+fn record(run: impl FnOnce()) -> AppMapObject {
+    let layer = AppMapLayer::new();
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, run);
+    let data = layer.test.lock().unwrap().data.clone();
+    data
+}
+
+/// The call event of `method`.
+fn call<'a>(events: &'a [EventObject], method: &str) -> (&'a EventObject, &'a CallObject) {
+    events
+        .iter()
+        .find_map(|x| match &x.event {
+            EventObjectType::Call(call) if call.method_id == method => Some((x, call)),
+            _ => None,
+        })
+        .unwrap()
+}
+
+fn current_thread_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+}
+
+fn multi_thread_runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn tasks_spawned_by_a_suspended_call_are_caused_by_it() {
+    let data = record(|| {
+        // the task first runs while `handle` waits for it
+        current_thread_runtime().block_on(
+            async {
+                tokio::spawn(
+                    async {}
+                        .instrument(info_span!("work"))
+                        .with_current_subscriber(),
+                )
+                .await
+                .unwrap();
+            }
+            .instrument(info_span!("handle")),
+        );
+    });
+    let (handle, _) = call(&data.events, "handle");
+    let (_, work) = call(&data.events, "work");
+    assert_eq!(work.caused_by, Some(vec![handle.id]));
+    assert!(data.events.iter().all(|x| match &x.event {
+        EventObjectType::Call(call) => call.method_id != "spawn",
+        _ => true,
+    }));
+}
+
+#[test]
+fn tasks_spawned_by_a_running_call_are_caused_by_a_spawn_event_inside_it() {
+    let data = record(|| {
+        let (started, wait) = mpsc::channel();
+        // `block_on` runs `handle` on this thread, which waits until `work` runs on a worker
+        multi_thread_runtime().block_on(
+            async move {
+                let task = tokio::spawn(
+                    async move { started.send(()).unwrap() }
+                        .instrument(info_span!("work"))
+                        .with_current_subscriber(),
+                );
+                wait.recv().unwrap();
+                task.await.unwrap();
+            }
+            .instrument(info_span!("handle")),
+        );
+    });
+    let (handle, handle_call) = call(&data.events, "handle");
+    let (spawn, spawn_call) = call(&data.events, "spawn");
+    let (work, work_call) = call(&data.events, "work");
+    assert_eq!(work_call.caused_by, Some(vec![spawn.id]));
+    assert_eq!(spawn.thread_id, handle.thread_id);
+    assert_ne!(work.thread_id, handle.thread_id);
+    assert_eq!(spawn_call.defined_class, handle_call.defined_class);
+    let task = &spawn_call.parameters.as_ref().unwrap()[0];
+    assert_eq!(task.value, "work");
+
+    // the spawn is nested in `handle`, which did not return yet when `work` was entered
+    let tree = CallTree::new(&data);
+    let parent = tree.node_for_event(spawn.id).unwrap().parent.unwrap();
+    assert_eq!(tree.nodes[parent].call.id, handle.id);
+}
+
+#[test]
+fn nested_calls_of_a_task_moved_to_another_thread_are_not_spawned() {
+    let data = record(|| {
+        let (resume, wait) = tokio::sync::oneshot::channel::<()>();
+        let mut handle = Box::pin(
+            async {
+                wait.await.unwrap();
+                async {}.instrument(info_span!("validate")).await;
+            }
+            .instrument(info_span!("handle")),
+        );
+        multi_thread_runtime().block_on(async move {
+            // `handle` is recorded on this thread, then continues on a worker
+            std::future::poll_fn(|cx| {
+                assert!(handle.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            resume.send(()).unwrap();
+            tokio::spawn(handle.with_current_subscriber())
+                .await
+                .unwrap();
+        });
+    });
+    let (handle, _) = call(&data.events, "handle");
+    let (validate, validate_call) = call(&data.events, "validate");
+    assert_ne!(validate.thread_id, handle.thread_id);
+    assert_eq!(validate_call.caused_by, None);
+    assert_eq!(data.events.len(), 4);
+}
+
+#[test]
+fn follows_from_is_recorded_as_cause() {
+    let data = record(|| {
+        let enqueue = info_span!("enqueue");
+        enqueue.in_scope(|| {});
+        let work = info_span!("work");
+        work.follows_from(&enqueue);
+        work.in_scope(|| {});
+    });
+    let (enqueue, _) = call(&data.events, "enqueue");
+    let (_, work) = call(&data.events, "work");
+    assert_eq!(work.caused_by, Some(vec![enqueue.id]));
+}
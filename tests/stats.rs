@@ -0,0 +1,84 @@
+mod common;
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::AppMap;
+use serde_json::json;
+
+use common::call;
+
+fn stats_of<'a>(stats: &'a [stats::FunctionStats], method: &str) -> &'a stats::FunctionStats {
+    stats.iter().find(|x| x.method_id == method).unwrap()
+}
+
+#[test]
+fn stats_count_calls_and_timings_per_function() {
+    let mut app_map = AppMap::new();
+    call(&mut app_map, 1, "create", 1.0, |app_map| {
+        call(app_map, 1, "validate", 0.25, |_| {});
+        call(app_map, 1, "validate", 0.5, |_| {});
+    });
+    call(&mut app_map, 2, "validate", 2.0, |_| {});
+
+    let stats = app_map.data.stats();
+    let names: Vec<&str> = stats
+        .functions
+        .iter()
+        .map(|x| x.method_id.as_str())
+        .collect();
+    assert_eq!(names, ["validate", "create"]);
+
+    let create = stats_of(&stats.functions, "create");
+    assert_eq!(create.calls, 1);
+    assert_eq!(create.total_elapsed, 1.0);
+    assert_eq!(create.self_elapsed, 0.25);
+    assert_eq!(create.max_depth, 0);
+
+    let validate = stats_of(&stats.functions, "validate");
+    assert_eq!(validate.calls, 3);
+    assert_eq!(validate.total_elapsed, 2.75);
+    assert_eq!(validate.mean_elapsed, 2.75 / 3.0);
+    assert_eq!(validate.p95_elapsed, 2.0);
+    assert_eq!(validate.max_depth, 1);
+    let threads: Vec<(u32, usize)> = validate
+        .threads
+        .iter()
+        .map(|x| (x.thread_id, x.calls))
+        .collect();
+    assert_eq!(threads, [(1, 2), (2, 1)]);
+
+    let table = stats.to_string();
+    assert!(table.starts_with("function"), "{}", table);
+    assert!(table.contains("my_app::orders::validate"), "{}", table);
+    assert!(table.contains("  thread 2"), "{}", table);
+}
+
+#[test]
+fn functions_called_repeatedly_are_in_the_class_map_once() {
+    let mut app_map = AppMap::new();
+    for _ in 0..3 {
+        call(&mut app_map, 1, "validate", 0.1, |_| {});
+    }
+    let json = serde_json::to_string(&app_map.data.class_map).unwrap();
+    assert_eq!(json.matches("\"validate\"").count(), 1, "{}", json);
+    assert_eq!(app_map.data.stats().functions.len(), 1);
+}
+
+#[test]
+fn functions_without_calls_are_listed_with_zero_calls() {
+    let data: AppMapObject = serde_json::from_value(json!({
+        "version": "1.12",
+        "classMap": [{
+            "type": "package", "name": "my_app",
+            "children": [{
+                "type": "class", "name": "orders",
+                "children": [{"type": "function", "name": "cancel", "static": true}]
+            }]
+        }],
+        "events": []
+    }))
+    .unwrap();
+    let stats = data.stats();
+    assert_eq!(stats.functions.len(), 1);
+    assert_eq!(stats.functions[0].calls, 0);
+    assert_eq!(stats.functions[0].total_elapsed, 0.0);
+}
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::streaming::{repair, AppMapStreamWriter, RepairOutcome};
+use appmap_tracing_test::{AppMap, AppMapLayer};
+use tracing::info_span;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// `create` calls `validate` twice. Timestamps are removed because they do not survive the
+/// round trip through JSON exactly.
+fn sample() -> AppMap {
+    let mut app_map = AppMap::new();
+    let create = app_map.add_function_call_event(
+        1,
+        "my_app::orders".to_string(),
+        "create".to_string(),
+        None,
+        None,
+        true,
+        None,
+    );
+    for _ in 0..2 {
+        let validate = app_map.add_function_call_event(
+            1,
+            "my_app::orders".to_string(),
+            "validate".to_string(),
+            None,
+            None,
+            true,
+            None,
+        );
+        app_map.add_function_return_event(1, validate, Some(0.1), ReturnObjectType::Normal);
+    }
+    app_map.add_function_return_event(1, create, Some(0.5), ReturnObjectType::Normal);
+    for event in app_map.data.events.iter_mut() {
+        event.timestamp = None;
+    }
+    app_map
+}
+
+fn json_of<T: serde::Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+fn streamed(app_map: &AppMap) -> String {
+    let mut writer = AppMapStreamWriter::new(vec![], "1.12").unwrap();
+    for event in app_map.data.events.iter() {
+        writer.write_event(event).unwrap();
+    }
+    assert_eq!(writer.written_events(), 6);
+    let bytes = writer.finish(&app_map.data.class_map, None).unwrap();
+    String::from_utf8(bytes).unwrap()
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}.appmap.json", name, std::process::id()))
+}
+
+#[test]
+fn streamed_maps_are_valid_json() {
+    let app_map = sample();
+    let json = streamed(&app_map);
+    assert_eq!(json.lines().count(), 8, "{}", json);
+    let read: AppMapObject = serde_json::from_str(&json).unwrap();
+    assert_eq!(json_of(&read), json_of(&app_map.data));
+}
+
+#[test]
+fn truncated_files_are_cut_after_the_last_complete_event() {
+    let app_map = sample();
+    let json = streamed(&app_map);
+    // cut in the middle of the fourth event, the call of the second `validate`
+    let fourth_event = json.match_indices('\n').nth(3).unwrap().0 + 1;
+    let path = temp_file("truncated");
+    std::fs::write(&path, &json[..fourth_event + 10]).unwrap();
+
+    assert_eq!(
+        repair(&path).unwrap(),
+        RepairOutcome::Repaired {
+            recovered_events: 3
+        }
+    );
+    let repaired = AppMapObject::read_from_file(&path).unwrap();
+    assert_eq!(
+        json_of(&repaired.events),
+        json_of(&app_map.data.events[..3])
+    );
+    // the class map was rebuilt from the call events
+    assert_eq!(repaired.class_map, app_map.data.class_map);
+
+    assert_eq!(repair(&path).unwrap(), RepairOutcome::AlreadyComplete);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn files_that_are_not_streamed_maps_are_not_repaired() {
+    let path = temp_file("not-streamed");
+    std::fs::write(&path, "not json\n").unwrap();
+    assert!(repair(&path).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "not json\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn a_streaming_layer_writes_its_file_on_finish() {
+    let path = temp_file("layer");
+    let layer = AppMapLayer::streaming(&path).unwrap();
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!(target: "my_app::orders", "create").in_scope(|| {
+            info_span!(target: "my_app::orders", "validate").in_scope(|| {});
+        });
+    });
+    layer.finish().unwrap();
+
+    let written = AppMapObject::read_from_file(&path).unwrap();
+    assert_eq!(written.events.len(), 4);
+    assert!(layer.test.lock().unwrap().data.events.is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn events_of_an_unfinished_layer_can_be_repaired() {
+    let path = temp_file("unfinished");
+    let layer = AppMapLayer::streaming(&path).unwrap();
+    let subscriber = Registry::default().with(layer.clone());
+    tracing::subscriber::with_default(subscriber, || {
+        info_span!(target: "my_app::orders", "create").in_scope(|| {});
+    });
+
+    // the layer is never finished, as if the process was killed
+    assert_eq!(
+        repair(&path).unwrap(),
+        RepairOutcome::Repaired {
+            recovered_events: 2
+        }
+    );
+    assert_eq!(AppMapObject::read_from_file(&path).unwrap().events.len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
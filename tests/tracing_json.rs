@@ -0,0 +1,140 @@
+use appmap_tracing_test::appmap_definition::*;
+use appmap_tracing_test::convert::tracing_json::{from_tracing_json, read_tracing_json};
+
+/// 2024-05-01T12:00:00Z
+const NOON: f64 = 1_714_564_800.0;
+
+/// Every event as "call <thread> <class>::<method>(<parameters>)" or
+/// "return <thread> <method> <return value>".
+fn summary(data: &AppMapObject) -> Vec<String> {
+    let mut methods = std::collections::HashMap::new();
+    data.events
+        .iter()
+        .map(|event| match &event.event {
+            EventObjectType::Call(call) => {
+                methods.insert(event.id, call.method_id.clone());
+                let parameters: Vec<String> = call
+                    .parameters
+                    .iter()
+                    .flatten()
+                    .map(|x| {
+                        format!(
+                            "{}: {} = {}",
+                            x.name.as_deref().unwrap_or_default(),
+                            x.class,
+                            x.value
+                        )
+                    })
+                    .collect();
+                format!(
+                    "call {} {}::{}({})",
+                    event.thread_id,
+                    call.defined_class,
+                    call.method_id,
+                    parameters.join(", ")
+                )
+            }
+            EventObjectType::Return(ret) => {
+                let return_value = match &ret.data {
+                    ReturnObjectType::Function(function) => function
+                        .return_value
+                        .as_ref()
+                        .map(|x| format!(" {}: {}", x.class, x.value)),
+                    _ => None,
+                };
+                format!(
+                    "return {} {}{}",
+                    event.thread_id,
+                    methods[&ret.parent_id],
+                    return_value.unwrap_or_default()
+                )
+            }
+        })
+        .collect()
+}
+
+fn timestamps(data: &AppMapObject) -> Vec<f64> {
+    data.events
+        .iter()
+        .map(|x| x.timestamp.unwrap() - NOON)
+        .collect()
+}
+
+fn elapsed(data: &AppMapObject) -> Vec<f64> {
+    data.events
+        .iter()
+        .filter_map(|x| match &x.event {
+            EventObjectType::Return(ret) => ret.elapsed,
+            _ => None,
+        })
+        .collect()
+}
+
+fn assert_close(actual: &[f64], expected: &[f64]) {
+    assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+    for (actual, expected) in actual.iter().zip(expected) {
+        assert!((actual - expected).abs() < 1e-6, "{:?}", actual);
+    }
+}
+
+#[test]
+fn spans_are_told_apart_by_their_fields_and_return_on_close() {
+    let data = read_tracing_json("tests/fixtures/orders.log.json").unwrap();
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 my_app::orders::create(order_id: u64 = 7)",
+            "call 2 my_app::orders::create(order_id: u64 = 8)",
+            "call 1 my_app::orders::validate(order_id: u64 = 7)",
+            "return 1 validate",
+            // entered again on another thread, but returned on the thread of its call
+            "return 1 create u64: 42",
+            "return 2 create",
+        ]
+    );
+    assert_close(&elapsed(&data), &[0.1, 0.7, 0.8]);
+    let create = match &data.events[0].event {
+        EventObjectType::Call(call) => call,
+        _ => unreachable!(),
+    };
+    assert_eq!(create.path.as_deref(), Some("src/orders.rs".as_ref()));
+    assert_eq!(create.lineno, Some(10));
+}
+
+#[test]
+fn timestamps_with_offsets_are_converted_to_utc() {
+    let data = read_tracing_json("tests/fixtures/orders.log.json").unwrap();
+    assert_close(&timestamps(&data), &[0.0, 0.1, 0.2, 0.3, 0.7, 0.9]);
+}
+
+#[test]
+fn spans_return_on_exit_without_close_events() {
+    let data = read_tracing_json("tests/fixtures/poll.log.json").unwrap();
+    // every poll of the span is a call of its own, on thread 1 as the log has no threads
+    assert_eq!(
+        summary(&data),
+        [
+            "call 1 my_app::worker::poll()",
+            "return 1 poll",
+            "call 1 my_app::worker::poll()",
+            "return 1 poll",
+        ]
+    );
+    assert_close(&timestamps(&data), &[0.0, 1.0, 2.0, 4.0]);
+    assert_close(&elapsed(&data), &[1.0, 2.0]);
+}
+
+#[test]
+fn threads_are_numbered_in_the_order_they_appear() {
+    let log = [
+        r#"{"fields":{"message":"enter"},"target":"app","span":{"name":"a"},"threadName":"worker-2"}"#,
+        r#"{"fields":{"message":"enter"},"target":"app","span":{"name":"b"},"threadName":"main"}"#,
+        r#"{"fields":{"message":"enter"},"target":"app","span":{"name":"c"},"threadName":"worker-2"}"#,
+    ]
+    .join("\n");
+    let data = from_tracing_json(log.as_bytes()).unwrap();
+    let threads: Vec<u32> = data.events.iter().map(|x| x.thread_id).collect();
+    assert_eq!(threads, [1, 2, 1]);
+    // spans which are never closed have no return
+    assert_eq!(data.events.len(), 3);
+}